@@ -0,0 +1,136 @@
+use crate::html::escape_attribute;
+
+// Attribute list written after a block or a link: `{#id .class key=value key="quoted value"}`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Attributes {
+    pub(crate) id: String,
+    pub(crate) classes: Vec<String>,
+    pub(crate) pairs: Vec<(String, String)>,
+}
+
+fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_:.".contains(c))
+}
+
+impl Attributes {
+    // Parses a whole `{...}` string. Anything but `.class`, `#id` and `key=value` tokens means
+    // it's an ordinary text in braces
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let mut rest = s.strip_prefix('{')?.strip_suffix('}')?.trim();
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut res = Attributes::default();
+
+        while !rest.is_empty() {
+            let token_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+
+            if let Some(class) = rest[..token_len].strip_prefix('.') {
+                if !is_name(class) {
+                    return None;
+                }
+                res.classes.push(class.to_string());
+                rest = &rest[token_len..];
+            } else if let Some(id) = rest[..token_len].strip_prefix('#') {
+                if !is_name(id) {
+                    return None;
+                }
+                res.id = id.to_string();
+                rest = &rest[token_len..];
+            } else {
+                let (key, after_eq) = rest.split_once('=')?;
+                if !is_name(key) {
+                    return None;
+                }
+
+                let (value, len) = match after_eq.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = after_eq[1..].find(quote)?;
+                        (&after_eq[1..end + 1], end + 2)
+                    }
+                    _ => {
+                        let end = after_eq.find(char::is_whitespace).unwrap_or(after_eq.len());
+                        (&after_eq[..end], end)
+                    }
+                };
+
+                match key {
+                    "id" => res.id = value.to_string(),
+                    "class" => res
+                        .classes
+                        .extend(value.split_whitespace().map(str::to_string)),
+                    _ => res.pairs.push((key.to_string(), value.to_string())),
+                }
+                rest = &after_eq[len..];
+            }
+
+            rest = rest.trim_start();
+        }
+
+        Some(res)
+    }
+
+    // Splits an attribute list off the end of a line. Returns the line before it
+    pub(crate) fn split_trailing(line: &str) -> Option<(&str, Attributes)> {
+        let line = line.trim_end();
+        if !line.ends_with('}') {
+            return None;
+        }
+
+        let start = line.rfind('{')?;
+        Some((&line[..start], Attributes::parse(&line[start..])?))
+    }
+
+    // Renders as ` id="x" class="a b" key="value"`, so it can follow a tag name in a template
+    pub(crate) fn render(&self) -> String {
+        let mut res = String::new();
+
+        if !self.id.is_empty() {
+            res += &format!(" id=\"{}\"", escape_attribute(&self.id));
+        }
+        if !self.classes.is_empty() {
+            res += &format!(" class=\"{}\"", escape_attribute(&self.classes.join(" ")));
+        }
+        for (key, value) in &self.pairs {
+            res += &format!(" {}=\"{}\"", key, escape_attribute(value));
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let attributes = Attributes::parse(r#"{ .a #x  .b data-n=1 title="Say 'hi'" class=c }"#);
+        assert_eq!(
+            attributes,
+            Some(Attributes {
+                id: "x".to_string(),
+                classes: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+                pairs: vec![
+                    ("data-n".to_string(), "1".to_string()),
+                    ("title".to_string(), "Say 'hi'".to_string()),
+                ],
+            })
+        );
+        assert_eq!(
+            attributes.unwrap().render(),
+            r#" id="x" class="a b c" data-n="1" title="Say &#39;hi&#39;""#
+        );
+
+        for text in ["{}", "{name}", "{.}", "{a=\"b}", "{#x y}", "{\"x\"=1}"] {
+            assert_eq!(Attributes::parse(text), None, "{text}");
+        }
+
+        let (line, attributes) = Attributes::split_trailing("Title {#top}  ").unwrap();
+        assert_eq!((line, attributes.id.as_str()), ("Title ", "top"));
+        assert!(Attributes::split_trailing("f(x) {x}").is_none());
+    }
+}
@@ -0,0 +1,31 @@
+// Backtick runs of code spans, shared by inline processing and comment stripping
+
+pub(crate) fn count_backticks(s: &str) -> usize {
+    s.chars().take_while(|&c| c == '`').count()
+}
+
+// Code span is closed by a backtick string of exactly the same length as the opening one
+pub(crate) fn find_closing_backticks(text: &str, length: usize) -> Option<usize> {
+    let mut i = 0;
+    while let Some(pos) = text[i..].find('`') {
+        let start = i + pos;
+        let run = count_backticks(&text[start..]);
+        if run == length {
+            return Some(start);
+        }
+        i = start + run;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_closing_backticks() {
+        assert_eq!(count_backticks("``a`"), 2);
+        assert_eq!(find_closing_backticks("a ` b``", 2), Some(5));
+        assert_eq!(find_closing_backticks("a ``` b", 2), None);
+    }
+}
@@ -1,43 +1,125 @@
-use std::collections::HashSet;
+use crate::html::{entity_len, escape_text};
+use crate::languages::{find_language, find_language_or_generic, Language};
+use crate::themes::{theme_style, Theme};
+use std::collections::HashMap;
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum HighlightClass {
     Keyword,
     Literal,
     Comment,
     Call,
+    Type,
+    Macro,
+    Attribute,
+    Preprocessor,
+    Operator,
+    Punctuation,
+    Variable,
+    Key,
+    Tag,
+    AttributeName,
     DiffAdd,
     DiffRemove,
+    DiffHeader,
+    DiffHunk,
+    DiffContext,
+    DiffAddWord,
+    DiffRemoveWord,
 }
 
-struct MultilineCommentDesc {
-    start: &'static str,
-    end: &'static str,
+impl HighlightClass {
+    // Key in the `[highlight]` section and a suffix of the default `code-*` class
+    fn name(self) -> &'static str {
+        match self {
+            HighlightClass::Keyword => "keyword",
+            HighlightClass::Literal => "literal",
+            HighlightClass::Comment => "comment",
+            HighlightClass::Call => "call",
+            HighlightClass::Type => "type",
+            HighlightClass::Macro => "macro",
+            HighlightClass::Attribute => "attribute",
+            HighlightClass::Preprocessor => "preprocessor",
+            HighlightClass::Operator => "operator",
+            HighlightClass::Punctuation => "punctuation",
+            HighlightClass::Variable => "variable",
+            HighlightClass::Key => "key",
+            HighlightClass::Tag => "tag",
+            HighlightClass::AttributeName => "attr-name",
+            HighlightClass::DiffAdd => "diff-add",
+            HighlightClass::DiffRemove => "diff-remove",
+            HighlightClass::DiffHeader => "diff-header",
+            HighlightClass::DiffHunk => "diff-hunk",
+            HighlightClass::DiffContext => "diff-context",
+            HighlightClass::DiffAddWord => "diff-add-word",
+            HighlightClass::DiffRemoveWord => "diff-remove-word",
+        }
+    }
 }
 
-struct CommentsDesc {
-    oneline: Vec<&'static str>,
-    multiline: Vec<MultilineCommentDesc>,
+// Markup of highlighted parts of code, set in the `[highlight]` section
+#[derive(Clone, Debug, Default)]
+pub(crate) struct HighlightMarkup {
+    // templates per class name: `keyword = '<span class="hljs-keyword">{text}</span>'`
+    templates: HashMap<String, String>,
+    // colours of a built-in theme put into `style` attributes instead of classes
+    inline_theme: Option<Theme>,
+    // changed words of paired removed and added diff lines are marked
+    word_diff: bool,
 }
 
-impl From<HighlightClass> for &str {
-    fn from(val: HighlightClass) -> Self {
-        match val {
-            HighlightClass::Keyword => "<span class=\"code-keyword\">",
-            HighlightClass::Literal => "<span class=\"code-literal\">",
-            HighlightClass::Comment => "<span class=\"code-comment\">",
-            HighlightClass::Call => "<span class=\"code-call\">",
-            HighlightClass::DiffAdd => "<span class=\"code-diff-add\">",
-            HighlightClass::DiffRemove => "<span class=\"code-diff-remove\">",
+impl HighlightMarkup {
+    pub(crate) fn new(
+        templates: HashMap<String, String>,
+        inline_theme: Option<Theme>,
+        word_diff: bool,
+    ) -> Self {
+        HighlightMarkup {
+            templates,
+            inline_theme,
+            word_diff,
         }
     }
+
+    // Opening and closing markup around a highlighted part
+    fn tags(&self, class: HighlightClass) -> (String, String) {
+        let name = class.name();
+        let template = match (self.templates.get(name), self.inline_theme) {
+            (Some(template), _) => template.clone(),
+            (None, Some(theme)) => theme_style(theme, name).map_or("{text}".to_string(), |style| {
+                format!("<span style=\"{style}\">{{text}}</span>")
+            }),
+            (None, None) => format!("<span class=\"code-{name}\">{{text}}</span>"),
+        };
+
+        let (open, close) = template.split_once("{text}").unwrap_or((&template, ""));
+        (open.to_string(), close.to_string())
+    }
+
+    // CSS selector of a highlighted part: `.code-keyword` by default or the classes of its
+    // template, `<span class="token comment">` is `.token.comment`. Parts without classes have
+    // none
+    pub(crate) fn selector(&self, name: &str) -> Option<String> {
+        let template = match self.templates.get(name) {
+            Some(template) => template,
+            None if self.inline_theme.is_some() => return None,
+            None => return Some(format!(".code-{name}")),
+        };
+        let open = template.split_once("{text}").map_or("", |(open, _)| open);
+        let (_, classes) = open.split_once("class=\"")?;
+        let (classes, _) = classes.split_once('"')?;
+        let selector = classes
+            .split_whitespace()
+            .map(|x| format!(".{x}"))
+            .collect::<String>();
+        (!selector.is_empty()).then_some(selector)
+    }
 }
 
 struct HighlightData {
     highlight_class: HighlightClass,
     start: usize,
     end: usize,
-    dead: bool,
 }
 
 impl HighlightData {
@@ -46,433 +128,1550 @@ impl HighlightData {
             highlight_class,
             start,
             end,
-            ..Default::default()
         }
     }
 }
 
-impl Default for HighlightData {
-    fn default() -> Self {
-        HighlightData {
-            highlight_class: HighlightClass::Keyword,
-            start: 0,
-            end: 0,
-            dead: false,
-        }
-    }
-}
-
-fn cplusplus_keywords() -> HashSet<&'static str> {
-    let keywords = [
-        "alignas",
-        "alignof",
-        "and",
-        "and_eq",
-        "asm",
-        "atomic_cancel",
-        "atomic_commit",
-        "atomic_noexcept",
-        "auto",
-        "bitand",
-        "bitor",
-        "bool",
-        "break",
-        "case",
-        "catch",
-        "char",
-        "char8_t",
-        "char16_t",
-        "char32_t",
-        "class",
-        "compl",
-        "concept",
-        "const",
-        "consteval",
-        "constexpr",
-        "const_cast",
-        "continue",
-        "co_await",
-        "co_return",
-        "co_yield",
-        "decltype",
-        "default",
-        "delete",
-        "do",
-        "double",
-        "dynamic_cast",
-        "else",
-        "enum",
-        "explicit",
-        "export",
-        "extern",
-        "false",
-        "float",
-        "for",
-        "friend",
-        "goto",
-        "if",
-        "inline",
-        "int",
-        "long",
-        "mutable",
-        "namespace",
-        "new",
-        "noexcept",
-        "not",
-        "not_eq",
-        "nullptr",
-        "operator",
-        "or",
-        "or_eq",
-        "private",
-        "protected",
-        "public",
-        "register",
-        "reinterpret_cast",
-        "requires",
-        "return",
-        "short",
-        "signed",
-        "sizeof",
-        "static",
-        "static_assert",
-        "static_cast",
-        "struct",
-        "switch",
-        "template",
-        "this",
-        "thread_local",
-        "throw",
-        "true",
-        "try",
-        "typedef",
-        "typeid",
-        "typename",
-        "union",
-        "unsigned",
-        "using",
-        "virtual",
-        "void",
-        "volatile",
-        "wchar_t",
-        "while",
-        "xor",
-        "xor_eq",
-        "define",    // Defines a macro
-        "undef",     // Undefines a macro
-        "include",   // Includes a file
-        "if",        // Starts a conditional directive
-        "ifdef",     // Checks if a macro is defined
-        "ifndef",    // Checks if a macro is not defined
-        "else",      // Provides an alternative for #if
-        "elif",      // Else-if condition for #if
-        "endif",     // Ends a conditional directive
-        "error",     // Generates a compile-time error
-        "pragma",    // Special compiler instructions
-        "line",      // Changes the current line number
-        "warning",   // Generates a compile-time warning
-        "region",    // Marks the start of a region (non-standard)
-        "endregion", // Marks the end of a region (non-standard)
-    ];
-    HashSet::from(keywords)
-}
-
-fn rust_keywords() -> HashSet<&'static str> {
-    let keywords = [
-        "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn",
-        "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
-        "return", "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe",
-        "use", "where", "while", "async", "await", "dyn",
-    ];
-    HashSet::from(keywords)
-}
-
-fn javascript_keywords() -> HashSet<&'static str> {
-    let keywords = [
-        "await",
-        "break",
-        "case",
-        "catch",
-        "class",
-        "const",
-        "continue",
-        "debugger",
-        "default",
-        "delete",
-        "do",
-        "else",
-        "enum",
-        "export",
-        "extends",
-        "false",
-        "finally",
-        "for",
-        "function",
-        "if",
-        "import",
-        "in",
-        "instanceof",
-        "let",
-        "new",
-        "null",
-        "return",
-        "super",
-        "switch",
-        "this",
-        "throw",
-        "true",
-        "try",
-        "typeof",
-        "var",
-        "void",
-        "while",
-        "with",
-        "yield",
-    ];
-    HashSet::from(keywords)
-}
-
-fn python_keywords() -> HashSet<&'static str> {
-    let keywords = [
-        "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
-        "continue", "def", "del", "elif", "else", "except", "finally", "for", "from", "global",
-        "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return",
-        "try", "while", "with", "yield",
-    ];
-    HashSet::from(keywords)
-}
-
-// Function to calculate the union of all keyword sets
-fn all_keywords() -> HashSet<&'static str> {
-    let mut all_keywords = cplusplus_keywords();
-    all_keywords.extend(rust_keywords());
-    all_keywords.extend(javascript_keywords());
-    all_keywords.extend(python_keywords());
-    all_keywords
-}
-
 type Lang = String;
 type Code = String;
 
-pub fn highlight_code(lang: &str, text: &str) -> (Lang, Code) {
+// Highlights raw code and returns it escaped
+pub(crate) fn highlight_code(
+    lang: &str,
+    text: &str,
+    user_languages: &[Language],
+    markup: &HighlightMarkup,
+) -> (Lang, Code) {
     let (lang, diff_mode) = if let Some(new_lang) = lang.strip_suffix(" diff") {
         (new_lang, true)
     } else {
         (lang, false)
     };
 
-    let mut corrected_lang = lang.to_lowercase();
-    let mut result = text.to_owned();
+    let language = find_language_or_generic(lang, user_languages);
+    let corrected_lang = if language.name.is_empty() {
+        lang.to_lowercase()
+    } else {
+        language.name.clone()
+    };
 
-    if lang == "js" {
-        corrected_lang = "javascript".to_owned();
-    } else if lang == "py" {
-        corrected_lang = "python".to_owned();
+    // `cpp diff` is a diff of C++ code, a plain `diff` has no code highlighting
+    if diff_mode || language.name == "diff" {
+        let code_language = diff_mode.then_some(language);
+        let result = highlight_diff(text, code_language, user_languages, markup);
+        return (corrected_lang, result);
     }
 
-    let code_indices = get_highlight_indices(&corrected_lang, &result);
-    apply_highlighting(code_indices, &mut result);
+    let code_indices = merge_neighbours(text, parse_code(text, language, user_languages));
+    (
+        corrected_lang,
+        render_highlights(code_indices, text, markup),
+    )
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Length of a number literal at the beginning of `code`: `0xFF`, `1_000`, `2.5e-3`, `10u8`
+fn number_len(code: &str, language: &Language) -> usize {
+    let is_separator = |c: char| language.number_separators.contains(&c);
+    let digits_len = |s: &str, is_digit: fn(&char) -> bool| {
+        s.find(|c: char| !is_digit(&c) && !is_separator(c))
+            .unwrap_or(s.len())
+    };
+
+    let prefix = language.number_prefixes.iter().find(|prefix| {
+        code.get(..prefix.len())
+            .is_some_and(|x| x.eq_ignore_ascii_case(prefix))
+    });
 
-    if diff_mode {
-        let diff_indices = get_diff_indices(&result);
-        apply_highlighting(diff_indices, &mut result);
+    let mut len = if let Some(prefix) = prefix {
+        prefix.len() + digits_len(&code[prefix.len()..], char::is_ascii_hexdigit)
+    } else {
+        let mut len = digits_len(code, char::is_ascii_digit);
+        if code[len..].starts_with('.') && code[len + 1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            len += 1 + digits_len(&code[len + 1..], char::is_ascii_digit);
+        }
+        let exponent = code[len..]
+            .strip_prefix(['e', 'E'])
+            .map(|x| x.strip_prefix(['+', '-']).unwrap_or(x));
+        if let Some(exponent) = exponent.filter(|x| x.starts_with(|c: char| c.is_ascii_digit())) {
+            len = code.len() - exponent.len();
+            len += digits_len(exponent, char::is_ascii_digit);
+        }
+        len
+    };
+
+    let rest = &code[len..];
+    let word_len = rest.find(|c: char| !is_word_char(c)).unwrap_or(rest.len());
+    if language
+        .number_suffixes
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(&rest[..word_len]))
+    {
+        len += word_len;
     }
 
-    (corrected_lang, result)
+    len
+}
+
+struct StringLiteral<'a> {
+    len: usize,
+    delimiter: &'a str,
+    // escapes are not processed: `r"C:\path"`
+    raw: bool,
+    // expressions in braces are interpolated: `f"{x}"`
+    format: bool,
+}
+
+fn find_prefix<'a>(code: &str, prefixes: &'a [String]) -> Option<&'a String> {
+    prefixes
+        .iter()
+        .filter(|x| {
+            code.get(..x.len())
+                .is_some_and(|p| p.eq_ignore_ascii_case(x))
+        })
+        .max_by_key(|x| x.len())
+}
+
+// Length of the body of a string up to and including `closing`, skipping escaped characters
+fn string_body_len(body: &str, closing: &str, escape: Option<&str>) -> Option<usize> {
+    let mut i = 0;
+    while i < body.len() {
+        let rest = &body[i..];
+        if rest.starts_with(closing) {
+            return Some(i + closing.len());
+        }
+        if let Some(escape) = escape.filter(|x| rest.starts_with(*x)) {
+            i += escape.len();
+            i += body[i..].chars().next().map_or(0, char::len_utf8);
+            continue;
+        }
+        i += rest.chars().next().unwrap().len_utf8();
+    }
+    None
 }
 
-fn get_highlight_indices(lang: &str, text: &str) -> Vec<HighlightData> {
-    let keywords = match lang {
-        "cpp" | "c" => cplusplus_keywords(),
-        "javascript" => javascript_keywords(),
-        "python" => python_keywords(),
-        "rust" => rust_keywords(),
-        _ => all_keywords(),
+// A string at the beginning of `code` with an optional prefix: `"a\"b"`, `b'x'`, `r#"raw"#`,
+// `f"{x}"`. It lasts up to the end of `code` if it's not closed, or up to the end of the line if
+// it's not a multiline one. The longest delimiter wins, so `"""` is not taken for an empty string
+// `""`
+fn parse_string<'a>(code: &str, language: &'a Language) -> Option<StringLiteral<'a>> {
+    let prefix = find_prefix(code, &language.string_prefixes);
+    let raw_prefix = find_prefix(code, &language.raw_string_prefixes);
+    let format_prefix = find_prefix(code, &language.format_string_prefixes);
+    let prefix_len = [prefix, raw_prefix, format_prefix]
+        .into_iter()
+        .flatten()
+        .map(String::len)
+        .max()
+        .unwrap_or(0);
+    let raw = raw_prefix.is_some_and(|x| x.len() == prefix_len);
+    let format = format_prefix.is_some_and(|x| x.len() == prefix_len);
+
+    // Rust raw strings may be wrapped into hashes: `r#"a "quoted" text"#`
+    let rest = &code[prefix_len..];
+    let hashes = if raw {
+        rest.len() - rest.trim_start_matches('#').len()
+    } else {
+        0
     };
+    let rest = &rest[hashes..];
+
+    let delimiter = language
+        .strings
+        .iter()
+        .filter(|x| rest.starts_with(x.as_str()))
+        .max_by_key(|x| x.len())?;
+    let raw = raw || language.raw_strings.contains(delimiter);
+    let escape = Some(language.escape.as_str()).filter(|x| !raw && !x.is_empty());
 
-    let comments_desc: CommentsDesc = match lang {
-        "python" => CommentsDesc {
-            oneline: vec!["#"],
-            multiline: vec![MultilineCommentDesc {
-                start: "'''",
-                end: "'''",
-            }],
-        },
-        _ => CommentsDesc {
-            oneline: vec!["//"],
-            multiline: vec![MultilineCommentDesc {
-                start: "/*",
-                end: "*/",
-            }],
-        },
+    let start_len = prefix_len + hashes + delimiter.len();
+    let closing = delimiter.clone() + &"#".repeat(hashes);
+    let end = if language.multiline_strings.contains(delimiter) {
+        code.len()
+    } else {
+        code[start_len..]
+            .find('\n')
+            .map_or(code.len(), |x| start_len + x)
     };
+    let body_len = string_body_len(&code[start_len..end], &closing, escape);
 
-    parse_code(text, keywords, comments_desc)
+    // a char literal is one character or escape long, otherwise it's a Rust lifetime `'a`
+    if language.char_literals.contains(delimiter) {
+        let body_len = body_len?;
+        let body = &code[start_len..start_len + body_len - closing.len()];
+        let is_escape = escape.is_some_and(|x| body.starts_with(x));
+        if body.is_empty() || (!is_escape && body.chars().count() > 1) {
+            return None;
+        }
+    }
+
+    Some(StringLiteral {
+        len: body_len.map_or(end, |len| start_len + len),
+        delimiter,
+        raw,
+        format,
+    })
 }
 
-// DISCLAIMER:
-// ChatGPT-4 written function. Potentially can be optimized
-fn parse_code(
+fn string_len(code: &str, language: &Language) -> Option<usize> {
+    parse_string(code, language).map(|x| x.len)
+}
+
+// Length of a block comment at the beginning of `code` if it's closed. Rust comments may be nested:
+// `/* a /* b */ c */`
+fn block_comment_len(code: &str, start: &str, end: &str, language: &Language) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < code.len() {
+        let rest = &code[i..];
+        if rest.starts_with(start) && (depth == 0 || language.nested_comments) {
+            depth += 1;
+            i += start.len();
+        } else if rest.starts_with(end) {
+            depth -= 1;
+            i += end.len();
+            if depth == 0 {
+                return Some(i);
+            }
+        } else {
+            i += rest.chars().next().unwrap().len_utf8();
+        }
+    }
+
+    None
+}
+
+// Length of a word after one of `prefixes`. `extra_chars` may occur inside the word
+fn prefixed_word_len(code: &str, prefixes: &[String], extra_chars: &[char]) -> Option<usize> {
+    let prefix = prefixes.iter().find(|x| code.starts_with(x.as_str()))?;
+    let rest = &code[prefix.len()..];
+    if !rest.starts_with(is_word_char) {
+        return None;
+    }
+
+    let is_name_char = |c: char| is_word_char(c) || extra_chars.contains(&c);
+    let name = &rest[..rest.find(|c| !is_name_char(c)).unwrap_or(rest.len())];
+    Some(prefix.len() + name.trim_end_matches(|c| !is_word_char(c)).len())
+}
+
+// Length of an attribute with its brackets: `#[cfg(feature = "x")]`. Nested brackets and strings
+// inside are skipped
+fn attribute_len(code: &str, language: &Language) -> Option<usize> {
+    let (open, close) = language
+        .attributes
+        .iter()
+        .filter(|(open, _)| code.starts_with(open.as_str()))
+        .max_by_key(|(open, _)| open.len())?;
+    let mut depth = 0;
+    let mut i = open.len();
+
+    while i < code.len() {
+        let rest = &code[i..];
+        if depth == 0 && rest.starts_with(close.as_str()) {
+            return Some(i + close.len());
+        }
+        if let Some(len) = string_len(rest, language) {
+            i += len;
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap();
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' if depth == 0 => return None,
+            ')' | ']' | '}' => depth -= 1,
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+
+    None
+}
+
+// Length of a preprocessor line without a trailing comment: `#include <vector> // std`.
+// A line ending with `\` is continued by the next one
+fn preprocessor_len(code: &str, language: &Language) -> Option<usize> {
+    let prefix = language
+        .preprocessor
+        .iter()
+        .find(|x| code.starts_with(x.as_str()))?;
+    if !code[prefix.len()..]
+        .trim_start_matches([' ', '\t'])
+        .starts_with(is_word_char)
+    {
+        return None;
+    }
+
+    let mut len = 0;
+    loop {
+        let rest = &code[len..];
+        let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        let comment = language
+            .line_comments
+            .iter()
+            .chain(language.block_comments.iter().map(|(start, _)| start))
+            .filter_map(|marker| line.find(marker.as_str()))
+            .filter(|&x| x > 0)
+            .min();
+        if let Some(comment) = comment {
+            return Some(len + line[..comment].trim_end().len());
+        }
+        if line.len() == rest.len() || !line.trim_end().ends_with('\\') {
+            return Some(len + line.trim_end().len());
+        }
+        len += line.len() + 1;
+    }
+}
+
+// `$HOME`, `${HOME}`, `$?` or `$"Node"`. Strings after a prefix are not taken inside other strings
+fn variable_len(code: &str, language: &Language, inside_string: bool) -> Option<usize> {
+    let prefix = language
+        .variable_prefixes
+        .iter()
+        .find(|x| code.starts_with(x.as_str()))?;
+    let rest = &code[prefix.len()..];
+
+    if rest.starts_with('{') {
+        return rest.find('}').map(|end| prefix.len() + end + 1);
+    }
+    if !inside_string {
+        if let Some(len) = string_len(rest, language) {
+            return Some(prefix.len() + len);
+        }
+    }
+    if let Some(c) = rest
+        .chars()
+        .next()
+        .filter(|c| language.variable_specials.contains(c))
+    {
+        return Some(prefix.len() + c.len_utf8());
+    }
+
+    prefixed_word_len(code, &language.variable_prefixes, &language.variable_chars)
+}
+
+// A string split into literal parts, variables interpolated into it and expressions in braces of
+// format strings
+fn string_highlights(
     code: &str,
-    keywords: HashSet<&str>,
-    comments_desc: CommentsDesc,
+    start: usize,
+    string: &StringLiteral,
+    language: &Language,
+    user_languages: &[Language],
 ) -> Vec<HighlightData> {
+    let end = start + string.len;
+    let interpolated = language
+        .interpolated_strings
+        .iter()
+        .any(|x| x == string.delimiter);
+    if !interpolated && !string.format {
+        return vec![HighlightData::new(HighlightClass::Literal, start, end)];
+    }
+
+    let mut res = vec![];
+    let mut literal_start = start;
+    let mut i = start;
+
+    let push_literal = |res: &mut Vec<HighlightData>, literal_start: usize, literal_end: usize| {
+        if literal_start < literal_end {
+            res.push(HighlightData::new(
+                HighlightClass::Literal,
+                literal_start,
+                literal_end,
+            ));
+        }
+    };
+
+    while i < end {
+        let rest = &code[i..end];
+
+        if !string.raw && !language.escape.is_empty() && rest.starts_with(&language.escape) {
+            i += language.escape.len();
+            i += code[i..end].chars().next().map_or(0, char::len_utf8);
+        } else if let Some(len) = interpolated
+            .then(|| variable_len(rest, language, true))
+            .flatten()
+        {
+            push_literal(&mut res, literal_start, i);
+            res.push(HighlightData::new(HighlightClass::Variable, i, i + len));
+            i += len;
+            literal_start = i;
+        } else if string.format && rest.starts_with("{{") {
+            i += 2;
+        } else if string.format && rest.starts_with('{') {
+            let (inner, len) = braced(rest);
+            push_literal(&mut res, literal_start, i + 1);
+            res.extend(shift(parse_code(inner, language, user_languages), i + 1));
+            // the closing brace goes to the literal
+            literal_start = i + 1 + inner.len();
+            i += len;
+        } else {
+            i += rest.chars().next().unwrap().len_utf8();
+        }
+    }
+
+    push_literal(&mut res, literal_start, end);
+    res
+}
+
+// Length of a `[section]` header which takes the whole line
+fn section_len(line: &str, language: &Language) -> Option<usize> {
+    language.sections.iter().find_map(|(open, close)| {
+        let end =
+            line.strip_prefix(open.as_str())?.find(close.as_str())? + open.len() + close.len();
+        let rest = line[end..].trim_start();
+        (rest.is_empty()
+            || language
+                .line_comments
+                .iter()
+                .any(|x| rest.starts_with(x.as_str())))
+        .then_some(end)
+    })
+}
+
+// Length of a key at the beginning of `code` in config formats: `key = value`, `"key": value`
+fn key_len(code: &str, language: &Language) -> Option<usize> {
+    if language.key_separators.is_empty() {
+        return None;
+    }
+
+    let quoted_len = string_len(code, language);
+    let len = quoted_len.or_else(|| {
+        let is_key_char = |c: char| is_word_char(c) || language.key_chars.contains(&c);
+        code.starts_with(is_word_char)
+            .then(|| code.find(|c| !is_key_char(c)).unwrap_or(code.len()))
+    })?;
+
+    let rest = code[len..].trim_start_matches([' ', '\t']);
+    let separator = rest
+        .chars()
+        .next()
+        .filter(|c| language.key_separators.contains(c))?;
+
+    // as in YAML, a bare key is followed by `: ` so `http://url` is not a key
+    let after = &rest[separator.len_utf8()..];
+    if separator == ':'
+        && quoted_len.is_none()
+        && !after.is_empty()
+        && !after.starts_with(char::is_whitespace)
+    {
+        return None;
+    }
+
+    Some(len)
+}
+
+fn shift(highlights: Vec<HighlightData>, offset: usize) -> impl Iterator<Item = HighlightData> {
+    highlights
+        .into_iter()
+        .map(move |x| HighlightData::new(x.highlight_class, x.start + offset, x.end + offset))
+}
+
+// `<div`, `</div` or a JSX fragment `<>`
+fn is_tag_start(code: &str) -> bool {
+    let name = code
+        .strip_prefix("</")
+        .or_else(|| code.strip_prefix('<'))
+        .unwrap_or_default();
+    code.starts_with('<')
+        && (name.starts_with(|c: char| c.is_alphabetic()) || name.starts_with('>'))
+}
+
+// In JSX `<` starts an element where an expression is expected, not after an operand: `a < b`,
+// `Array<T>`
+fn is_jsx_position(code_before: &str) -> bool {
+    let before = code_before.trim_end();
+    before.is_empty()
+        || before.ends_with(['(', '=', '{', '}', '[', ',', ':', '?', '>', '&', '|', ';'])
+        || before.ends_with("return")
+}
+
+// TSX generic arrow functions: `<T,>(x: T) => x` or `<T extends object>`
+fn is_type_parameters(code: &str) -> bool {
+    let name_len = code[1..]
+        .find(|c: char| !is_word_char(c))
+        .map_or(code.len(), |x| x + 1);
+    let rest = code[name_len..].trim_start();
+    rest.starts_with(',') || rest.starts_with("extends ")
+}
+
+// A block in braces at the beginning of `code`: `{a: {b}}`. Returns its content and the whole
+// length, up to the end of `code` if the block is not closed
+fn braced(code: &str) -> (&str, usize) {
+    let mut depth = 0;
+    for (i, c) in code.char_indices() {
+        if c == '{' {
+            depth += 1;
+        } else if c == '}' {
+            depth -= 1;
+            if depth == 0 {
+                return (&code[1..i], i + 1);
+            }
+        }
+    }
+    (&code[1..], code.len())
+}
+
+// Highlights a tag at the beginning of `code` shifted by `offset`: its name, attribute names and
+// values. JSX expressions in braces are highlighted as `language`. Returns the tag name, whether
+// it's a closing or a self-closing tag and its length
+fn parse_tag<'a>(
+    code: &'a str,
+    offset: usize,
+    language: &Language,
+    user_languages: &[Language],
+    highlights: &mut Vec<HighlightData>,
+) -> (&'a str, bool, bool, usize) {
+    let is_name_char = |c: char| is_word_char(c) || "-:.".contains(c);
+
+    let name_start = if code.starts_with("</") { 2 } else { 1 };
+    let name_len = code[name_start..]
+        .find(|c| !is_name_char(c))
+        .unwrap_or(code.len() - name_start);
+    let name = &code[name_start..name_start + name_len];
+    highlights.push(HighlightData::new(
+        HighlightClass::Tag,
+        offset,
+        offset + name_start + name_len,
+    ));
+
+    let mut i = name_start + name_len;
+    let mut self_closing = false;
+
+    while i < code.len() {
+        let rest = &code[i..];
+        let c = rest.chars().next().unwrap();
+
+        if rest.starts_with("/>") || c == '>' {
+            self_closing = c == '/';
+            let len = if self_closing { 2 } else { 1 };
+            highlights.push(HighlightData::new(
+                HighlightClass::Tag,
+                offset + i,
+                offset + i + len,
+            ));
+            i += len;
+            break;
+        } else if c == '{' {
+            let (inner, len) = braced(rest);
+            highlights.extend(shift(
+                parse_code(inner, language, user_languages),
+                offset + i + 1,
+            ));
+            i += len;
+        } else if c == '"' || c == '\'' {
+            let len = rest[1..].find(c).map_or(rest.len(), |end| end + 2);
+            highlights.push(HighlightData::new(
+                HighlightClass::Literal,
+                offset + i,
+                offset + i + len,
+            ));
+            i += len;
+        } else if is_name_char(c) || c == '@' {
+            let len = rest
+                .find(|c: char| !is_name_char(c) && c != '@')
+                .unwrap_or(rest.len());
+            // a name after `=` is an unquoted value
+            let class = if code[..i].trim_end().ends_with('=') {
+                HighlightClass::Literal
+            } else {
+                HighlightClass::AttributeName
+            };
+            highlights.push(HighlightData::new(class, offset + i, offset + i + len));
+            i += len;
+        } else {
+            i += c.len_utf8();
+        }
+    }
+
+    (name, code.starts_with("</"), self_closing, i)
+}
+
+// Highlights markup: tags, comments, entities and languages embedded into `<script>` and
+// `<style>`. With `element_only` it's a JSX element at the beginning of `code`: parsing stops
+// after its closing tag and text in braces is highlighted as `language`. Returns highlights shifted
+// by `offset` and the length of the parsed code
+fn parse_markup(
+    code: &str,
+    offset: usize,
+    language: &Language,
+    user_languages: &[Language],
+    element_only: bool,
+) -> (Vec<HighlightData>, usize) {
+    let mut highlights = vec![];
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < code.len() {
+        let rest = &code[i..];
+
+        if let Some((start, end)) = language
+            .block_comments
+            .iter()
+            .find(|(start, _)| rest.starts_with(start.as_str()))
+        {
+            let len = rest[start.len()..]
+                .find(end.as_str())
+                .map_or(rest.len(), |x| start.len() + x + end.len());
+            highlights.push(HighlightData::new(
+                HighlightClass::Comment,
+                offset + i,
+                offset + i + len,
+            ));
+            i += len;
+        } else if is_tag_start(rest) {
+            let (name, closing, self_closing, len) =
+                parse_tag(rest, offset + i, language, user_languages, &mut highlights);
+            i += len;
+
+            if closing {
+                depth -= 1;
+            } else if !self_closing {
+                depth += 1;
+            }
+            if element_only && depth <= 0 {
+                break;
+            }
+
+            // `<script>` content is highlighted as JavaScript, `<style>` as CSS
+            let embedded = language
+                .embedded
+                .iter()
+                .find(|(tag, _)| !closing && tag.eq_ignore_ascii_case(name))
+                .and_then(|(_, lang)| find_language(lang, user_languages));
+            if let Some(embedded) = embedded {
+                let closing_tag = format!("</{name}");
+                let len = code[i..]
+                    .to_ascii_lowercase()
+                    .find(&closing_tag.to_ascii_lowercase())
+                    .unwrap_or(code.len() - i);
+                highlights.extend(shift(
+                    parse_code(&code[i..i + len], embedded, user_languages),
+                    offset + i,
+                ));
+                i += len;
+            }
+        } else if let Some(len) = rest.starts_with('&').then(|| entity_len(rest)).flatten() {
+            highlights.push(HighlightData::new(
+                HighlightClass::Literal,
+                offset + i,
+                offset + i + len,
+            ));
+            i += len;
+        } else if element_only && rest.starts_with('{') {
+            let (inner, len) = braced(rest);
+            highlights.extend(shift(
+                parse_code(inner, language, user_languages),
+                offset + i + 1,
+            ));
+            i += len;
+        } else {
+            i += rest.chars().next().unwrap().len_utf8();
+        }
+    }
+
+    (highlights, i)
+}
+
+// DISCLAIMER:
+// ChatGPT-4 written function. Potentially can be optimized
+fn parse_code(code: &str, language: &Language, user_languages: &[Language]) -> Vec<HighlightData> {
+    if language.tags == "markup" {
+        return parse_markup(code, 0, language, user_languages, false).0;
+    }
+
     let mut highlights = Vec::new();
     let mut i = 0;
 
     while i < code.len() {
         let remainder = &code[i..];
+        // text of the line before and after the current position
+        let line_start = code[code[..i].rfind('\n').map_or(0, |x| x + 1)..i].trim();
+        let line = &remainder[..remainder.find('\n').unwrap_or(remainder.len())];
 
-        // Check for one-line comments
-        if comments_desc
-            .oneline
+        // Check for multiline comments. They go first: `--[[` is not a Lua line comment
+        if let Some((start_marker, end_marker)) = language
+            .block_comments
             .iter()
-            .find_map(|&marker| remainder.strip_prefix(marker))
-            .is_some()
+            .find(|(start, _)| remainder.starts_with(start.as_str()))
         {
-            if let Some(end) = remainder.find('\n') {
-                highlights.push(HighlightData::new(HighlightClass::Comment, i, i + end));
-                i += end;
+            if let Some(len) = block_comment_len(remainder, start_marker, end_marker, language) {
+                highlights.push(HighlightData::new(HighlightClass::Comment, i, i + len));
+                i += len;
             } else {
                 highlights.push(HighlightData::new(HighlightClass::Comment, i, code.len()));
                 break;
             }
         }
-        // Check for multiline comments
-        else if let Some(end_marker) = comments_desc
-            .multiline
+        // Preprocessor directives take the whole line: `#include <vector>`
+        else if let Some(len) = line_start
+            .is_empty()
+            .then(|| preprocessor_len(remainder, language))
+            .flatten()
+        {
+            highlights.push(HighlightData::new(HighlightClass::Preprocessor, i, i + len));
+            i += len;
+        }
+        // Check for one-line comments
+        else if language
+            .line_comments
             .iter()
-            .find_map(|desc| remainder.strip_prefix(desc.start).map(|_| desc.end))
+            .any(|marker| remainder.starts_with(marker.as_str()))
         {
-            if let Some(end) = remainder.find(end_marker) {
-                highlights.push(HighlightData::new(
-                    HighlightClass::Comment,
-                    i,
-                    i + end + end_marker.len(),
-                ));
-                i += end + end_marker.len();
+            if let Some(end) = remainder.find('\n') {
+                highlights.push(HighlightData::new(HighlightClass::Comment, i, i + end));
+                i += end;
             } else {
                 highlights.push(HighlightData::new(HighlightClass::Comment, i, code.len()));
                 break;
             }
         }
+        // Sections and keys of config formats. A key starts a line, an inline table, a list item or
+        // a CSS declaration
+        else if let Some(len) = line_start
+            .is_empty()
+            .then(|| section_len(line, language))
+            .flatten()
+        {
+            highlights.push(HighlightData::new(HighlightClass::Keyword, i, i + len));
+            i += len;
+        } else if let Some(len) = (line_start.is_empty()
+            || line_start.ends_with(['{', ',', '-', ';']))
+        .then(|| key_len(remainder, language))
+        .flatten()
+        {
+            highlights.push(HighlightData::new(HighlightClass::Key, i, i + len));
+            i += len;
+        }
         // Check for literals (strings, chars, numbers)
-        else if let Some(stripped) = remainder.strip_prefix('"') {
-            if let Some(end) = stripped.find('"') {
-                highlights.push(HighlightData::new(
-                    HighlightClass::Literal,
-                    i,
-                    i + end + 2, // Account for the closing quote
-                ));
-                i += end + 2;
-            } else {
-                highlights.push(HighlightData::new(HighlightClass::Literal, i, code.len()));
-                break;
-            }
-        } else if let Some(stripped) = remainder.strip_prefix('\'') {
-            if let Some(end) = stripped.find('\'') {
-                highlights.push(HighlightData::new(
-                    HighlightClass::Literal,
-                    i,
-                    i + end + 2, // Account for the closing quote
-                ));
-                i += end + 2;
-            } else {
-                highlights.push(HighlightData::new(HighlightClass::Literal, i, code.len()));
-                break;
-            }
+        else if let Some(string) = parse_string(remainder, language).filter(|x| {
+            language.multiline_strings.iter().any(|d| d == x.delimiter)
+                || !code[..i].ends_with(is_word_char)
+        }) {
+            highlights.extend(string_highlights(
+                code,
+                i,
+                &string,
+                language,
+                user_languages,
+            ));
+            i += string.len;
         } else if remainder.starts_with(|c: char| c.is_ascii_digit()) {
-            // Numbers (simple detection of integers and floats)
-            let mut end = 0;
-            while end < remainder.len()
-                && remainder[end..].starts_with(|c: char| c.is_ascii_digit() || c == '.')
-            {
-                end += 1;
-            }
+            let end = number_len(remainder, language);
             highlights.push(HighlightData::new(HighlightClass::Literal, i, i + end));
             i += end;
         }
+        // JSX elements: `return <div className="x">{text}</div>`
+        else if let Some((tag_highlights, len)) = (language.tags == "jsx"
+            && is_jsx_position(&code[..i])
+            && is_tag_start(remainder)
+            && !is_type_parameters(remainder))
+        .then(|| parse_markup(remainder, i, language, user_languages, true))
+        {
+            highlights.extend(tag_highlights);
+            i += len;
+        }
+        // Annotations and attributes: `@export`, `#[derive(Debug)]`
+        else if let Some(len) = prefixed_word_len(remainder, &language.annotations, &[])
+            .or_else(|| attribute_len(remainder, language))
+        {
+            highlights.push(HighlightData::new(HighlightClass::Attribute, i, i + len));
+            i += len;
+        }
+        // Variables and node paths: `$HOME`, `$Player/Sprite`, `$"Path"`
+        else if let Some(len) =
+            variable_len(remainder, language, false).filter(|_| !code[..i].ends_with(is_word_char))
+        {
+            highlights.push(HighlightData::new(HighlightClass::Variable, i, i + len));
+            i += len;
+        }
         // Check for keywords
-        else if remainder.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
-            let mut end = 0;
-            while end < remainder.len()
-                && remainder[end..].starts_with(|c: char| c.is_alphanumeric() || c == '_')
-            {
-                end += 1;
-            }
+        else if remainder.starts_with(is_word_char) {
+            let mut end = remainder
+                .find(|c: char| !is_word_char(c))
+                .unwrap_or(remainder.len());
             let word = &remainder[..end];
-            if keywords.contains(word) {
+            let after = &remainder[end..];
+            let is_macro = !language.macro_suffix.is_empty()
+                && after.starts_with(language.macro_suffix.as_str())
+                && !after[language.macro_suffix.len()..].starts_with('=');
+            if language.is_keyword(word) {
                 highlights.push(HighlightData::new(HighlightClass::Keyword, i, i + end));
-            } else if remainder[end..].starts_with('(') {
+            } else if language.types.contains(word) {
+                highlights.push(HighlightData::new(HighlightClass::Type, i, i + end));
+            } else if is_macro {
+                // Macro call with its suffix: `println!`
+                end += language.macro_suffix.len();
+                highlights.push(HighlightData::new(HighlightClass::Macro, i, i + end));
+            } else if after.starts_with('(') {
                 // Function call
                 highlights.push(HighlightData::new(HighlightClass::Call, i, i + end));
+            } else if language.is_type(word) {
+                highlights.push(HighlightData::new(HighlightClass::Type, i, i + end));
             }
             i += end;
         } else {
             // Advance character by character to avoid breaking UTF-8 sequences
             let next_char = remainder.chars().next().unwrap();
-            i += next_char.len_utf8();
+            let end = i + next_char.len_utf8();
+            if language.operators.contains(&next_char) {
+                highlights.push(HighlightData::new(HighlightClass::Operator, i, end));
+            } else if language.punctuation.contains(&next_char) {
+                highlights.push(HighlightData::new(HighlightClass::Punctuation, i, end));
+            }
+            i = end;
         }
     }
 
-    for i in 1..highlights.len() {
-        let (left, right) = highlights.split_at_mut(i);
-        let left = &mut left[0];
-        let right = &mut right[0];
+    highlights
+}
+
+// Joins neighbours of the same class separated by nothing or a space: `unsigned int`
+fn merge_neighbours(code: &str, highlights: Vec<HighlightData>) -> Vec<HighlightData> {
+    let mut res: Vec<HighlightData> = Vec::with_capacity(highlights.len());
 
-        if left.highlight_class == right.highlight_class && left.end + 1 == right.start {
-            left.end = right.end;
-            right.dead = true;
+    for data in highlights {
+        if let Some(last) = res.last_mut() {
+            let gap = &code[last.end..data.start];
+            if last.highlight_class == data.highlight_class
+                && (gap.is_empty() || gap == " " || gap == "\t")
+            {
+                last.end = data.end;
+                continue;
+            }
         }
+        res.push(data);
     }
 
-    highlights
-        .into_iter()
-        .filter(|x| !x.dead)
-        .collect::<Vec<_>>()
+    res
 }
 
-fn get_diff_indices(text: &str) -> Vec<HighlightData> {
-    let mut highlights = Vec::new();
-    let mut line_start = 0;
+// Escapes the code and wraps highlighted parts into spans
+fn render_highlights(indices: Vec<HighlightData>, text: &str, markup: &HighlightMarkup) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut pos = 0;
 
-    for line in text.lines() {
-        let line_length = line.len();
+    for data in indices {
+        let (open, close) = markup.tags(data.highlight_class);
+        res += &escape_text(&text[pos..data.start]);
+        res += &open;
+        res += &escape_text(&text[data.start..data.end]);
+        res += &close;
+        pos = data.end;
+    }
 
-        if line.starts_with('+') {
-            let line_end = line_start + line_length;
-            highlights.push(HighlightData::new(
-                HighlightClass::DiffAdd,
-                line_start,
-                line_end,
-            ));
+    res += &escape_text(&text[pos..]);
+    res
+}
+
+// Sizes of the old and new parts of a hunk: `@@ -1,5 +1,6 @@` is `(5, 6)`, a missing size is 1
+fn hunk_sizes(line: &str) -> Option<(usize, usize)> {
+    let mut ranges = line.strip_prefix("@@")?.split_whitespace();
+    let mut size = |sign: char| -> Option<usize> {
+        let range = ranges.next()?.strip_prefix(sign)?;
+        range
+            .split_once(',')
+            .map_or(Some(1), |(_, len)| len.parse().ok())
+    };
+    Some((size('-')?, size('+')?))
+}
+
+// Classes of unified diff lines. Lines which are not headers, additions, removals or context lines
+// starting with a space are left as is, so `cpp diff` blocks may skip the context marker
+fn classify_diff_lines(lines: &[&str]) -> Vec<Option<HighlightClass>> {
+    let mut res = Vec::with_capacity(lines.len());
+    // lines of the old and new file left in the current hunk
+    let mut hunk: Option<(usize, usize)> = None;
+    // lines after `diff --git` are headers up to the first hunk
+    let mut in_header = false;
+
+    for (i, line) in lines.iter().enumerate() {
+        let next = lines.get(i + 1).unwrap_or(&"");
+        let prev = if i > 0 { lines[i - 1] } else { "" };
+
+        let class = if line.starts_with("@@") {
+            hunk = Some(hunk_sizes(line).unwrap_or((usize::MAX, usize::MAX)));
+            in_header = false;
+            HighlightClass::DiffHunk
+        } else if let Some((old, new)) = hunk.as_mut() {
+            let class = if line.starts_with('-') {
+                *old = old.saturating_sub(1);
+                HighlightClass::DiffRemove
+            } else if line.starts_with('+') {
+                *new = new.saturating_sub(1);
+                HighlightClass::DiffAdd
+            } else if line.starts_with('\\') {
+                // `\ No newline at end of file`
+                HighlightClass::Comment
+            } else if line.is_empty() || line.starts_with(' ') {
+                // an empty line is a context line which lost its trailing space
+                *old = old.saturating_sub(1);
+                *new = new.saturating_sub(1);
+                HighlightClass::DiffContext
+            } else {
+                res.push(None);
+                continue;
+            };
+            if *old == 0 && *new == 0 {
+                hunk = None;
+            }
+            class
+        } else if line.starts_with("diff ") {
+            in_header = true;
+            HighlightClass::DiffHeader
+        } else if in_header
+            || (line.starts_with("---") && next.starts_with("+++"))
+            || (line.starts_with("+++") && prev.starts_with("---"))
+        {
+            HighlightClass::DiffHeader
+        } else if line.starts_with('-') {
+            HighlightClass::DiffRemove
+        } else if line.starts_with('+') {
+            HighlightClass::DiffAdd
+        } else {
+            res.push(None);
+            continue;
+        };
+        res.push(Some(class));
+    }
+
+    res
+}
+
+// Words, runs of whitespace and single other characters with their positions
+fn diff_tokens(line: &str) -> Vec<(usize, &str)> {
+    let mut res = vec![];
+    let mut i = 0;
+
+    while i < line.len() {
+        let rest = &line[i..];
+        let c = rest.chars().next().unwrap();
+        let len = if is_word_char(c) {
+            rest.find(|c: char| !is_word_char(c))
+        } else if c.is_whitespace() {
+            rest.find(|c: char| !c.is_whitespace())
+        } else {
+            Some(c.len_utf8())
         }
+        .unwrap_or(rest.len());
+        res.push((i, &rest[..len]));
+        i += len;
+    }
 
-        if line.starts_with('-') {
-            let line_end = line_start + line_length;
-            highlights.push(HighlightData::new(
-                HighlightClass::DiffRemove,
-                line_start,
-                line_end,
-            ));
+    res
+}
+
+// Byte ranges inside a line
+type Ranges = Vec<(usize, usize)>;
+
+// Changed parts of a removed and an added line, found by the longest common subsequence of their
+// tokens. Lines without common words are not compared
+fn word_changes(old: &str, new: &str) -> Option<(Ranges, Ranges)> {
+    const MAX_TABLE_SIZE: usize = 250_000;
+
+    let old_tokens = diff_tokens(old);
+    let new_tokens = diff_tokens(new);
+    let (n, m) = (old_tokens.len(), new_tokens.len());
+    if (n + 1) * (m + 1) > MAX_TABLE_SIZE {
+        return None;
+    }
+
+    // lcs[i][j] is the common subsequence length of the tokens starting at `i` and `j`
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_tokens[i].1 == new_tokens[j].1 {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
         }
+    }
 
-        // Update the start index for the next line
-        line_start += line_length + 1; // +1 for the '\n' character
+    let mut old_common = vec![false; n];
+    let mut new_common = vec![false; m];
+    let mut has_common_word = false;
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old_tokens[i].1 == new_tokens[j].1 {
+            old_common[i] = true;
+            new_common[j] = true;
+            has_common_word |= !old_tokens[i].1.trim().is_empty();
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    if !has_common_word {
+        return None;
     }
 
-    highlights
+    // neighbouring changes separated by whitespace are joined
+    let changes = |line: &str, tokens: &[(usize, &str)], common: &[bool]| {
+        let mut res: Ranges = vec![];
+        for (&(start, token), _) in tokens.iter().zip(common).filter(|(_, common)| !**common) {
+            match res.last_mut() {
+                Some(last) if line[last.1..start].trim().is_empty() => last.1 = start + token.len(),
+                _ => res.push((start, start + token.len())),
+            }
+        }
+        res
+    };
+    Some((
+        changes(old, &old_tokens, &old_common),
+        changes(new, &new_tokens, &new_common),
+    ))
 }
 
-fn apply_highlighting(indices: Vec<HighlightData>, text: &mut String) {
-    let mut offset_accum: usize = 0;
-    for data in indices {
-        let start_tag = data.highlight_class.into();
-        const END_TAG: &str = "</span>";
+// Escapes a line and wraps its parts into spans. `changes` are outer spans, `tokens` are split at
+// their bounds to keep the markup nested
+fn render_diff_line(
+    line: &str,
+    tokens: &[HighlightData],
+    changes: &[(usize, usize)],
+    change_class: HighlightClass,
+    markup: &HighlightMarkup,
+) -> String {
+    let mut bounds = vec![0, line.len()];
+    bounds.extend(tokens.iter().flat_map(|x| [x.start, x.end]));
+    bounds.extend(changes.iter().flat_map(|x| [x.0, x.1]));
+    bounds.sort();
+    bounds.dedup();
+
+    let (change_open, change_close) = markup.tags(change_class);
+    let mut res = String::new();
+    let mut inside_change = false;
+
+    for bound in bounds.windows(2) {
+        let (start, end) = (bound[0], bound[1]);
+        let is_change = changes.iter().any(|x| x.0 <= start && start < x.1);
+        if is_change != inside_change {
+            res += if is_change {
+                &change_open
+            } else {
+                &change_close
+            };
+            inside_change = is_change;
+        }
+
+        let text = escape_text(&line[start..end]);
+        match tokens.iter().find(|x| x.start <= start && start < x.end) {
+            Some(token) => {
+                let (open, close) = markup.tags(token.highlight_class);
+                res += &open;
+                res += &text;
+                res += &close;
+            }
+            None => res += &text,
+        }
+    }
+    if inside_change {
+        res += &change_close;
+    }
+
+    res
+}
+
+// Highlights a unified diff. Code after the line markers is highlighted with `language`
+fn highlight_diff(
+    text: &str,
+    language: Option<&Language>,
+    user_languages: &[Language],
+    markup: &HighlightMarkup,
+) -> String {
+    let lines = text.split('\n').collect::<Vec<_>>();
+    let classes = classify_diff_lines(&lines);
+
+    // code of every line without its marker, and the marker length
+    let bodies = lines
+        .iter()
+        .zip(&classes)
+        .map(|(line, class)| match class {
+            Some(
+                HighlightClass::DiffAdd | HighlightClass::DiffRemove | HighlightClass::DiffContext,
+            ) => {
+                let marker = line.chars().next().map_or(0, char::len_utf8);
+                (&line[marker..], marker)
+            }
+            Some(_) => ("", line.len()),
+            None => (*line, 0),
+        })
+        .collect::<Vec<_>>();
+
+    // code highlights split into lines, positions are relative to a line start
+    let mut tokens: Vec<Vec<HighlightData>> = lines.iter().map(|_| vec![]).collect();
+    if let Some(language) = language {
+        let code = bodies.iter().map(|x| x.0).collect::<Vec<_>>().join("\n");
+        let mut line_starts = vec![0];
+        line_starts.extend(code.match_indices('\n').map(|(i, _)| i + 1));
+
+        for data in merge_neighbours(&code, parse_code(&code, language, user_languages)) {
+            let mut start = data.start;
+            while start < data.end {
+                let line = line_starts.partition_point(|&x| x <= start) - 1;
+                let line_end = line_starts.get(line + 1).map_or(code.len(), |x| x - 1);
+                let end = data.end.min(line_end);
+                let to_line = |x: usize| x - line_starts[line] + bodies[line].1;
+                if start < end {
+                    tokens[line].push(HighlightData::new(
+                        data.highlight_class,
+                        to_line(start),
+                        to_line(end),
+                    ));
+                }
+                start = line_end + 1;
+            }
+        }
+    }
+
+    // changed words of removed lines followed by the same number of added ones
+    let mut changes: Vec<Ranges> = lines.iter().map(|_| vec![]).collect();
+    let mut i = 0;
+    while markup.word_diff && i < lines.len() {
+        let is_class = |i: usize, class| classes.get(i) == Some(&Some(class));
+        let removed = (i..)
+            .take_while(|&x| is_class(x, HighlightClass::DiffRemove))
+            .count();
+        let added = (i + removed..)
+            .take_while(|&x| is_class(x, HighlightClass::DiffAdd))
+            .count();
+
+        for k in 0..removed.min(added) {
+            let (old, new) = (i + k, i + removed + k);
+            if let Some((old_changes, new_changes)) =
+                word_changes(&lines[old][1..], &lines[new][1..])
+            {
+                changes[old] = old_changes.iter().map(|x| (x.0 + 1, x.1 + 1)).collect();
+                changes[new] = new_changes.iter().map(|x| (x.0 + 1, x.1 + 1)).collect();
+            }
+        }
+        i += (removed + added).max(1);
+    }
+
+    let mut res = String::with_capacity(text.len());
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            res.push('\n');
+        }
+
+        let change_class = if classes[i] == Some(HighlightClass::DiffAdd) {
+            HighlightClass::DiffAddWord
+        } else {
+            HighlightClass::DiffRemoveWord
+        };
+        let html = render_diff_line(line, &tokens[i], &changes[i], change_class, markup);
+        match classes[i] {
+            Some(class) => {
+                let (open, close) = markup.tags(class);
+                res += &open;
+                res += &html;
+                res += &close;
+            }
+            None => res += &html,
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_code() {
+        assert_eq!(
+            highlight_code("C++", "float x = 0.5F + 0x1F'FF; // half", &[], &Default::default()),
+            (
+                "cpp".to_string(),
+                "<span class=\"code-type\">float</span> x <span class=\"code-operator\">=</span> \
+                 <span class=\"code-literal\">0.5F</span> <span class=\"code-operator\">+</span> \
+                 <span class=\"code-literal\">0x1F'FF</span><span class=\"code-punctuation\">;</span> \
+                 <span class=\"code-comment\">// half</span>"
+                    .to_string()
+            )
+        );
+
+        let user_languages = vec![Language::parse(
+            "gd",
+            "[language]\nkeywords = 'func'\nline-comments = '#'\nstrings = '\"'",
+        )];
+        assert_eq!(
+            highlight_code(
+                "gd",
+                "func f(): # \"x\"",
+                &user_languages,
+                &Default::default()
+            )
+            .1,
+            "<span class=\"code-keyword\">func</span> <span class=\"code-call\">f</span>(): \
+             <span class=\"code-comment\"># \"x\"</span>"
+        );
+        assert_eq!(
+            highlight_code("Unknown", "", &[], &Default::default()).0,
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn test_gdscript() {
+        let code = "@export var speed := 1_0.5 # m/s\n\
+                    func _ready():\n\
+                    \t$Player/Sprite.hide()\n\
+                    \tvar doc = \"\"\"a \"quoted\" text\"\"\" % 5";
+        assert_eq!(
+            highlight_code("gd", code, &[], &Default::default()),
+            (
+                "gdscript".to_string(),
+                "<span class=\"code-attribute\">@export</span> <span class=\"code-keyword\">var</span> speed \
+                 <span class=\"code-operator\">:=</span> <span class=\"code-literal\">1_0.5</span> \
+                 <span class=\"code-comment\"># m/s</span>\n\
+                 <span class=\"code-keyword\">func</span> <span class=\"code-call\">_ready</span>\
+                 <span class=\"code-punctuation\">()</span><span class=\"code-operator\">:</span>\n\
+                 \t<span class=\"code-variable\">$Player/Sprite</span><span class=\"code-punctuation\">.</span>\
+                 <span class=\"code-call\">hide</span><span class=\"code-punctuation\">()</span>\n\
+                 \t<span class=\"code-keyword\">var</span> doc <span class=\"code-operator\">=</span> \
+                 <span class=\"code-literal\">\"\"\"a \"quoted\" text\"\"\"</span> % <span class=\"code-literal\">5</span>"
+                    .to_string()
+            )
+        );
+
+        assert_eq!(
+            highlight_code("gdshader", "uniform vec4 c : source_color; // tint", &[], &Default::default()).1,
+            "<span class=\"code-keyword\">uniform</span> <span class=\"code-type\">vec4</span> c \
+             <span class=\"code-operator\">:</span> <span class=\"code-keyword\">source_color</span>\
+             <span class=\"code-punctuation\">;</span> <span class=\"code-comment\">// tint</span>"
+        );
+    }
+
+    #[test]
+    fn test_shell_and_config_languages() {
+        assert_eq!(
+            highlight_code("sh", "echo \"$HOME/${dir}\" $# 'x$y' # done", &[], &Default::default()),
+            (
+                "bash".to_string(),
+                "echo <span class=\"code-literal\">\"</span><span class=\"code-variable\">$HOME</span>\
+                 <span class=\"code-literal\">/</span><span class=\"code-variable\">${dir}</span>\
+                 <span class=\"code-literal\">\"</span> <span class=\"code-variable\">$#</span> \
+                 <span class=\"code-literal\">'x$y'</span> <span class=\"code-comment\"># done</span>"
+                    .to_string()
+            )
+        );
+
+        assert_eq!(
+            highlight_code(
+                "toml",
+                "[tool.x]\nname = \"a\" # c\npoint = { x = 1 }",
+                &[],
+                &Default::default()
+            )
+            .1,
+            "<span class=\"code-keyword\">[tool.x]</span>\n\
+             <span class=\"code-key\">name</span> = <span class=\"code-literal\">\"a\"</span> \
+             <span class=\"code-comment\"># c</span>\n\
+             <span class=\"code-key\">point</span> = { <span class=\"code-key\">x</span> = \
+             <span class=\"code-literal\">1</span> }"
+        );
+        assert_eq!(
+            highlight_code("yml", "- url: http://x # y\n  on: *a yes", &[], &Default::default()).1,
+            "- <span class=\"code-key\">url</span>: http://x <span class=\"code-comment\"># y</span>\n  \
+             <span class=\"code-key\">on</span>: <span class=\"code-variable\">*a</span> \
+             <span class=\"code-keyword\">yes</span>"
+        );
+        assert_eq!(
+            highlight_code("json", "{\"a\": [\"b\", null]}", &[], &Default::default()).1,
+            "{<span class=\"code-key\">\"a\"</span>: [<span class=\"code-literal\">\"b\"</span>, \
+             <span class=\"code-keyword\">null</span>]}"
+        );
+    }
+
+    #[test]
+    fn test_web_languages() {
+        assert_eq!(
+            highlight_code("ts", "let n: number = a < b && c > 1 ? 0 : 1;", &[], &Default::default()),
+            (
+                "typescript".to_string(),
+                "<span class=\"code-keyword\">let</span> n<span class=\"code-operator\">:</span> \
+                 <span class=\"code-type\">number</span> <span class=\"code-operator\">=</span> \
+                 a <span class=\"code-operator\">&lt;</span> b <span class=\"code-operator\">&amp;&amp;</span> \
+                 c <span class=\"code-operator\">&gt;</span> <span class=\"code-literal\">1</span> \
+                 <span class=\"code-operator\">?</span> <span class=\"code-literal\">0</span> \
+                 <span class=\"code-operator\">:</span> <span class=\"code-literal\">1</span>\
+                 <span class=\"code-punctuation\">;</span>"
+                    .to_string()
+            )
+        );
+
+        assert_eq!(
+            highlight_code(
+                "html",
+                "<!-- c --><p class=\"x\" hidden>&amp; if</p>\n\
+                 <script>let x = 1;</script><style>p { color: red; }</style>",
+                &[],
+                &Default::default()
+            )
+            .1,
+            "<span class=\"code-comment\">&lt;!-- c --&gt;</span><span class=\"code-tag\">&lt;p</span> \
+             <span class=\"code-attr-name\">class</span>=<span class=\"code-literal\">\"x\"</span> \
+             <span class=\"code-attr-name\">hidden</span><span class=\"code-tag\">&gt;</span>\
+             <span class=\"code-literal\">&amp;amp;</span> if<span class=\"code-tag\">&lt;/p&gt;</span>\n\
+             <span class=\"code-tag\">&lt;script&gt;</span><span class=\"code-keyword\">let</span> x \
+             <span class=\"code-operator\">=</span> <span class=\"code-literal\">1</span>\
+             <span class=\"code-punctuation\">;</span><span class=\"code-tag\">&lt;/script&gt;&lt;style&gt;</span>\
+             p { <span class=\"code-key\">color</span>: red; }\
+             <span class=\"code-tag\">&lt;/style&gt;</span>"
+        );
+
+        assert_eq!(
+            highlight_code(
+                "tsx",
+                "const f = <T,>(x: Array<T>) => <Item id={x.length}>{x}</Item>;",
+                &[],
+                &Default::default()
+            )
+            .1,
+            "<span class=\"code-keyword\">const</span> f <span class=\"code-operator\">= &lt;</span>T\
+             <span class=\"code-punctuation\">,</span><span class=\"code-operator\">&gt;</span>\
+             <span class=\"code-punctuation\">(</span>x<span class=\"code-operator\">:</span> \
+             <span class=\"code-type\">Array</span><span class=\"code-operator\">&lt;</span>T\
+             <span class=\"code-operator\">&gt;</span><span class=\"code-punctuation\">)</span> \
+             <span class=\"code-operator\">=&gt;</span> <span class=\"code-tag\">&lt;Item</span> \
+             <span class=\"code-attr-name\">id</span>={x<span class=\"code-punctuation\">.</span>length}\
+             <span class=\"code-tag\">&gt;</span>{x}<span class=\"code-tag\">&lt;/Item&gt;</span>\
+             <span class=\"code-punctuation\">;</span>"
+        );
+    }
+
+    #[test]
+    fn test_systems_languages() {
+        assert_eq!(
+            highlight_code("cs", "int def = 0;", &[], &Default::default()).1,
+            "<span class=\"code-type\">int</span> def <span class=\"code-operator\">=</span> \
+             <span class=\"code-literal\">0</span><span class=\"code-punctuation\">;</span>"
+        );
+        assert_eq!(
+            highlight_code("lua", "--[[ a\nb ]] local x -- c", &[], &Default::default()).1,
+            "<span class=\"code-comment\">--[[ a\nb ]]</span> <span class=\"code-keyword\">local</span> x \
+             <span class=\"code-comment\">-- c</span>"
+        );
+        assert_eq!(
+            highlight_code("kt", "val s = \"$name!\"", &[], &Default::default()).1,
+            "<span class=\"code-keyword\">val</span> s <span class=\"code-operator\">=</span> \
+             <span class=\"code-literal\">\"</span>\
+             <span class=\"code-variable\">$name</span><span class=\"code-literal\">!\"</span>"
+        );
+        for (alias, lang) in [
+            ("frag", "glsl"),
+            ("golang", "go"),
+            ("fx", "hlsl"),
+            ("zig", "zig"),
+        ] {
+            assert_eq!(highlight_code(alias, "", &[], &Default::default()).0, lang);
+        }
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(
+            highlight_code("c", r#"puts("a \"b\" \\"); x"#, &[], &Default::default()).1,
+            r#"<span class="code-call">puts</span><span class="code-punctuation">(</span><span class="code-literal">"a \"b\" \\"</span><span class="code-punctuation">);</span> x"#
+        );
+        assert_eq!(
+            highlight_code("sh", r#"echo 'a\'; "\$x $y""#, &[], &Default::default()).1,
+            r#"echo <span class="code-literal">'a\'</span>; <span class="code-literal">"\$x </span><span class="code-variable">$y</span><span class="code-literal">"</span>"#
+        );
+    }
+
+    #[test]
+    fn test_unclosed_strings() {
+        assert_eq!(
+            highlight_code(
+                "yaml",
+                "title: Don't panic\nother: 1",
+                &[],
+                &Default::default()
+            )
+            .1,
+            "<span class=\"code-key\">title</span>: Don't panic\n\
+             <span class=\"code-key\">other</span>: <span class=\"code-literal\">1</span>"
+        );
+        assert_eq!(
+            highlight_code("ini", "msg = it's\nb = 2", &[], &Default::default()).1,
+            "<span class=\"code-key\">msg</span> = it's\n\
+             <span class=\"code-key\">b</span> = <span class=\"code-literal\">2</span>"
+        );
+        assert_eq!(
+            highlight_code(
+                "css",
+                "a::after { content: \"x }\nb { color: red }",
+                &[],
+                &Default::default()
+            )
+            .1,
+            "a::after { <span class=\"code-key\">content</span>: <span class=\"code-literal\">\"x }</span>\n\
+             b { <span class=\"code-key\">color</span>: red }"
+        );
+        assert_eq!(
+            highlight_code("py", "s = \"\"\"a\nb", &[], &Default::default()).1,
+            "s <span class=\"code-operator\">=</span> <span class=\"code-literal\">\"\"\"a\nb</span>"
+        );
+    }
+
+    #[test]
+    fn test_rust_lifetimes_and_raw_strings() {
+        assert_eq!(
+            highlight_code(
+                "rust",
+                r#"fn f<'a>(s: &'a str) -> char { '\'' }"#,
+                &[],
+                &Default::default()
+            )
+            .1,
+            r#"<span class="code-keyword">fn</span> f<span class="code-operator">&lt;</span>'a<span class="code-operator">&gt;</span><span class="code-punctuation">(</span>s<span class="code-operator">: &amp;</span>'a <span class="code-type">str</span><span class="code-punctuation">)</span> <span class="code-operator">-&gt;</span> <span class="code-type">char</span> <span class="code-punctuation">{</span> <span class="code-literal">'\''</span> <span class="code-punctuation">}</span>"#
+        );
+        assert_eq!(
+            highlight_code(
+                "rust",
+                r###"let s = r#"a "b" \"#; b'x'"###,
+                &[],
+                &Default::default()
+            )
+            .1,
+            r###"<span class="code-keyword">let</span> s <span class="code-operator">=</span> <span class="code-literal">r#"a "b" \"#</span><span class="code-punctuation">;</span> <span class="code-literal">b'x'</span>"###
+        );
+    }
+
+    #[test]
+    fn test_python_strings() {
+        assert_eq!(
+            highlight_code("py", "\"\"\"doc \"q\" \"\"\"\nf\"{x + y} {{y}}\", rb'\\d'", &[], &Default::default()).1,
+            "<span class=\"code-literal\">\"\"\"doc \"q\" \"\"\"</span>\n\
+             <span class=\"code-literal\">f\"{</span>x <span class=\"code-operator\">+</span> y\
+             <span class=\"code-literal\">} {{y}}\"</span><span class=\"code-punctuation\">,</span> \
+             <span class=\"code-literal\">rb'\\d'</span>"
+        );
+    }
+
+    #[test]
+    fn test_nested_comments() {
+        assert_eq!(
+            highlight_code("rust", "/* a /* b */ c */ x", &[], &Default::default()).1,
+            "<span class=\"code-comment\">/* a /* b */ c */</span> x"
+        );
+        assert_eq!(
+            highlight_code("c", "/* a /* b */ c */", &[], &Default::default()).1,
+            "<span class=\"code-comment\">/* a /* b */</span> c <span class=\"code-operator\">*/</span>"
+        );
+    }
+
+    #[test]
+    fn test_richer_classes() {
+        assert_eq!(
+            highlight_code(
+                "rust",
+                "#[derive(Debug)]\nlet v = vec![MAX, Vec::new()];",
+                &[],
+                &Default::default()
+            )
+            .1,
+            r#"<span class="code-attribute">#[derive(Debug)]</span>
+<span class="code-keyword">let</span> v <span class="code-operator">=</span> <span class="code-macro">vec!</span><span class="code-punctuation">[</span>MAX<span class="code-punctuation">,</span> <span class="code-type">Vec</span><span class="code-operator">::</span><span class="code-call">new</span><span class="code-punctuation">()];</span>"#
+        );
+        assert_eq!(
+            highlight_code(
+                "cpp",
+                "#include <vector> // std\n#define A(x) \\\n  x",
+                &[],
+                &Default::default()
+            )
+            .1,
+            "<span class=\"code-preprocessor\">#include &lt;vector&gt;</span> \
+             <span class=\"code-comment\">// std</span>\n\
+             <span class=\"code-preprocessor\">#define A(x) \\\n  x</span>"
+        );
+        assert_eq!(
+            highlight_code("py", "@dataclass\nclass Point: pass", &[], &Default::default()).1,
+            "<span class=\"code-attribute\">@dataclass</span>\n<span class=\"code-keyword\">class</span> \
+             <span class=\"code-type\">Point</span><span class=\"code-operator\">:</span> \
+             <span class=\"code-keyword\">pass</span>"
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let code =
+            "--- a/x\n+++ b/x\n@@ -1,3 +1,2 @@ fn\n a\n-b\n--- c\n\\ No newline at end of file";
+        assert_eq!(
+            highlight_code("patch", code, &[], &Default::default()),
+            (
+                "diff".to_string(),
+                r#"<span class="code-diff-header">--- a/x</span>
+<span class="code-diff-header">+++ b/x</span>
+<span class="code-diff-hunk">@@ -1,3 +1,2 @@ fn</span>
+<span class="code-diff-context"> a</span>
+<span class="code-diff-remove">-b</span>
+<span class="code-diff-remove">--- c</span>
+<span class="code-comment">\ No newline at end of file</span>"#
+                    .to_string()
+            )
+        );
+
+        // lines without a marker are not context, and markers are cut at char boundaries
+        assert_eq!(
+            highlight_code(
+                "diff",
+                "@@ -1,2 +1,2 @@\nébc\n é\n-a\n+b",
+                &[],
+                &Default::default()
+            )
+            .1,
+            "<span class=\"code-diff-hunk\">@@ -1,2 +1,2 @@</span>\nébc\n\
+             <span class=\"code-diff-context\"> é</span>\n\
+             <span class=\"code-diff-remove\">-a</span>\n<span class=\"code-diff-add\">+b</span>"
+        );
+        assert_eq!(
+            highlight_code(
+                "rust diff",
+                "@@ -1,2 +1,2 @@\nébc\n let é;\n-a\n+b",
+                &[],
+                &Default::default()
+            )
+            .1,
+            "<span class=\"code-diff-hunk\">@@ -1,2 +1,2 @@</span>\nébc\n\
+             <span class=\"code-diff-context\"> <span class=\"code-keyword\">let</span> é\
+             <span class=\"code-punctuation\">;</span></span>\n\
+             <span class=\"code-diff-remove\">-a</span>\n<span class=\"code-diff-add\">+b</span>"
+        );
+    }
 
-        text.insert_str(data.start + offset_accum, start_tag);
-        offset_accum += start_tag.len();
-        text.insert_str(data.end + offset_accum, END_TAG);
-        offset_accum += END_TAG.len();
+    #[test]
+    fn test_code_diff_with_changed_words() {
+        let markup = HighlightMarkup::new(HashMap::new(), None, true);
+        assert_eq!(
+            highlight_code(
+                "rust diff",
+                "fn f() {\n-    x(1);\n+    y(1);\n}",
+                &[],
+                &markup
+            )
+            .1,
+            r#"<span class="code-keyword">fn</span> <span class="code-call">f</span><span class="code-punctuation">() {</span>
+<span class="code-diff-remove">-    <span class="code-diff-remove-word"><span class="code-call">x</span></span><span class="code-punctuation">(</span><span class="code-literal">1</span><span class="code-punctuation">);</span></span>
+<span class="code-diff-add">+    <span class="code-diff-add-word"><span class="code-call">y</span></span><span class="code-punctuation">(</span><span class="code-literal">1</span><span class="code-punctuation">);</span></span>
+<span class="code-punctuation">}</span>"#
+        );
+        assert_eq!(
+            highlight_code("diff", "-a /* b\n+a c */", &[], &markup).1,
+            r#"<span class="code-diff-remove">-a <span class="code-diff-remove-word">/* b</span></span>
+<span class="code-diff-add">+a <span class="code-diff-add-word">c */</span></span>"#
+        );
     }
 }
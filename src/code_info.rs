@@ -0,0 +1,357 @@
+use crate::attributes::Attributes;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+// Fenced code info string: ```` ```rust title="main.rs" {3-5} linenos {.class} ````
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CodeInfo {
+    pub(crate) lang: String,
+    pub(crate) title: String,
+    pub(crate) highlighted_lines: Vec<RangeInclusive<usize>>,
+    // number of the first line when line numbers are on: `linenos` or `linenos=10`
+    pub(crate) line_numbers: Option<usize>,
+    pub(crate) attributes: Attributes,
+
+    // code taken from a file: ```` ```rust file=src/main.rs lines=10-30 ```` or `region=name`
+    pub(crate) file: String,
+    lines: Option<(usize, Option<usize>)>,
+    region: String,
+
+    // options which can't be parsed, like `linenos=x`
+    pub(crate) errors: Vec<String>,
+}
+
+// Splits by whitespace keeping `{...}` and quoted values whole
+fn tokens(info: &str) -> Vec<&str> {
+    let mut res = vec![];
+    let mut start: Option<usize> = None;
+    let mut closing: Option<char> = None;
+
+    for (i, c) in info.char_indices() {
+        if let Some(close) = closing {
+            if c == close {
+                closing = None;
+            }
+            continue;
+        }
+
+        match c {
+            _ if c.is_whitespace() => {
+                if let Some(start) = start.take() {
+                    res.push(&info[start..i]);
+                }
+            }
+            '{' => closing = Some('}'),
+            '"' | '\'' => closing = Some(c),
+            _ => {}
+        }
+
+        if !c.is_whitespace() && start.is_none() {
+            start = Some(i);
+        }
+    }
+
+    if let Some(start) = start {
+        res.push(&info[start..]);
+    }
+
+    res
+}
+
+fn unquote(s: &str) -> &str {
+    for quote in ['"', '\''] {
+        if s.len() >= 2 && s.starts_with(quote) && s.ends_with(quote) {
+            return &s[1..s.len() - 1];
+        }
+    }
+    s
+}
+
+// `10-30`, `10-` or `10`
+fn parse_file_lines(s: &str) -> Option<(usize, Option<usize>)> {
+    match s.split_once('-') {
+        Some((start, "")) => Some((start.parse().ok()?, None)),
+        Some((start, end)) => Some((start.parse().ok()?, Some(end.parse().ok()?))),
+        None => {
+            let line = s.parse().ok()?;
+            Some((line, Some(line)))
+        }
+    }
+}
+
+// `3-5,7` or `3-5 7`
+fn parse_line_ranges(s: &str) -> Option<Vec<RangeInclusive<usize>>> {
+    s.split([',', ' '])
+        .filter(|x| !x.is_empty())
+        .map(|range| match range.split_once('-') {
+            Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+            None => {
+                let line = range.trim().parse().ok()?;
+                Some(line..=line)
+            }
+        })
+        .collect()
+}
+
+impl CodeInfo {
+    pub(crate) fn parse(info: &str) -> Self {
+        let mut res = CodeInfo::default();
+
+        for (i, token) in tokens(info).into_iter().enumerate() {
+            if let Some(braced) = token.strip_prefix('{').and_then(|x| x.strip_suffix('}')) {
+                if let Some(ranges) = parse_line_ranges(braced) {
+                    res.highlighted_lines.extend(ranges);
+                } else if let Some(attributes) = Attributes::parse(token) {
+                    if !attributes.id.is_empty() {
+                        res.attributes.id = attributes.id;
+                    }
+                    res.attributes.classes.extend(attributes.classes);
+                    res.attributes.pairs.extend(attributes.pairs);
+                } else {
+                    res.errors
+                        .push(format!("invalid line ranges or attributes '{token}'"));
+                }
+            } else if let Some((key, value)) = token.split_once('=') {
+                let value = unquote(value);
+                match key {
+                    "title" => res.title = value.to_string(),
+                    "linenos" => match value.parse() {
+                        Ok(first) => res.line_numbers = Some(first),
+                        Err(_) => res
+                            .errors
+                            .push(format!("invalid first line number '{value}'")),
+                    },
+                    "file" => res.file = value.to_string(),
+                    "lines" => match parse_file_lines(value) {
+                        Some(lines) => res.lines = Some(lines),
+                        None => res.errors.push(format!("invalid line range '{value}'")),
+                    },
+                    "region" => res.region = value.to_string(),
+                    _ => {
+                        if let Some(attributes) = Attributes::parse(&format!("{{{token}}}")) {
+                            res.attributes.pairs.extend(attributes.pairs);
+                        }
+                    }
+                }
+            } else if token == "linenos" {
+                res.line_numbers = Some(1);
+            } else if i == 0 {
+                res.lang = token.to_string();
+            } else if token == "diff" && i == 1 {
+                // `cpp diff` is a diff of C++ code
+                res.lang += " diff";
+            }
+        }
+
+        // Pandoc-like ```` ```{.rust .class} ```` where the first class is a language
+        if res.lang.is_empty() && !res.attributes.classes.is_empty() {
+            res.lang = res.attributes.classes.remove(0);
+        }
+
+        res
+    }
+
+    // Reads the code of a `file=` block. A path is relative to `dir`, a directory of the Markdown
+    // file. Returns a diagnostic on failure
+    pub(crate) fn load_file(&self, dir: &Path) -> Result<String, String> {
+        let code = std::fs::read_to_string(dir.join(&self.file))
+            .map_err(|err| format!("can't include '{}': {}", self.file, err))?;
+        let lines = code.lines().collect::<Vec<_>>();
+
+        let lines = if !self.region.is_empty() {
+            let is_marker = |line: &str, marker: &str| {
+                line.split_once(marker)
+                    .is_some_and(|(_, name)| name.trim() == self.region)
+            };
+
+            let start = lines
+                .iter()
+                .position(|x| is_marker(x, "#region"))
+                .ok_or_else(|| format!("region '{}' not found in '{}'", self.region, self.file))?;
+            let len = lines[start + 1..]
+                .iter()
+                .position(|x| is_marker(x, "#endregion") || x.trim().ends_with("#endregion"))
+                .ok_or_else(|| {
+                    format!("region '{}' is not closed in '{}'", self.region, self.file)
+                })?;
+
+            &lines[start + 1..start + 1 + len]
+        } else if let Some((start, end)) = self.lines {
+            let end = end.unwrap_or(lines.len());
+            if start == 0 || start > end || end > lines.len() {
+                return Err(format!(
+                    "lines {}-{} are out of range of '{}' with {} lines",
+                    start,
+                    end,
+                    self.file,
+                    lines.len()
+                ));
+            }
+            &lines[start - 1..end]
+        } else {
+            &lines[..]
+        };
+
+        Ok(lines.join("\n"))
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted_lines.iter().any(|x| x.contains(&line))
+    }
+
+    // Wraps every line into a span with an optional line number, if any line options are set
+    pub(crate) fn render_lines(&self, html: &str) -> String {
+        if self.line_numbers.is_none() && self.highlighted_lines.is_empty() {
+            return html.to_string();
+        }
+
+        split_html_lines(html)
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let class = if self.is_highlighted(i + 1) {
+                    "code-line code-line-highlighted"
+                } else {
+                    "code-line"
+                };
+                let number = self.line_numbers.map_or(String::new(), |first| {
+                    format!("<span class=\"code-line-number\">{}</span>", first + i)
+                });
+                format!("<span class=\"{class}\">{number}{line}</span>")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// Splits highlighted code into lines. Tags spanning several lines, like multiline comments, are
+// closed at the end of a line and opened again on the next one
+fn split_html_lines(html: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    // opening tags and names of the elements which are not closed yet
+    let mut open: Vec<(&str, &str)> = vec![];
+    let mut i = 0;
+
+    while i < html.len() {
+        let rest = &html[i..];
+
+        if rest.starts_with('\n') {
+            for (_, name) in open.iter().rev() {
+                line += &format!("</{name}>");
+            }
+            lines.push(std::mem::take(&mut line));
+            for (tag, _) in &open {
+                line += tag;
+            }
+            i += 1;
+            continue;
+        }
+
+        if rest.starts_with('<') {
+            if let Some(end) = rest.find('>') {
+                let tag = &rest[..end + 1];
+                if tag.starts_with("</") {
+                    open.pop();
+                } else if !tag.ends_with("/>") {
+                    let name_end = tag[1..]
+                        .find(|c: char| c.is_whitespace() || c == '>')
+                        .map_or(tag.len(), |x| x + 1);
+                    open.push((tag, &tag[1..name_end]));
+                }
+                line += tag;
+                i += tag.len();
+                continue;
+            }
+        }
+
+        let c = rest.chars().next().unwrap();
+        line.push(c);
+        i += c.len_utf8();
+    }
+
+    lines.push(line);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TestDir;
+
+    #[test]
+    fn test_parse() {
+        let info =
+            CodeInfo::parse(r#"rust title="main file.rs" {3-5,7} linenos=10 {.wide #x} data-a=1"#);
+
+        assert_eq!(info.lang, "rust");
+        assert_eq!(info.title, "main file.rs");
+        assert_eq!(info.highlighted_lines, vec![3..=5, 7..=7]);
+        assert_eq!(info.line_numbers, Some(10));
+        assert_eq!(
+            info.attributes.render(),
+            r#" id="x" class="wide" data-a="1""#
+        );
+
+        assert_eq!(CodeInfo::parse("cpp diff").lang, "cpp diff");
+        assert_eq!(CodeInfo::parse("{.js .numbers} linenos").lang, "js");
+        assert_eq!(CodeInfo::parse("").lang, "");
+        assert_eq!(
+            CodeInfo::parse("c linenos=x {2-} lines=3-a").errors,
+            vec![
+                "invalid first line number 'x'",
+                "invalid line ranges or attributes '{2-}'",
+                "invalid line range '3-a'"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_file() {
+        let dir = TestDir::new("load_file");
+        std::fs::write(
+            dir.join("downtown_include_test.rs"),
+            "fn main() {\n    // #region body\n    run();\n    // #endregion\n}",
+        )
+        .unwrap();
+
+        let load = |info: &str| CodeInfo::parse(info).load_file(dir.path());
+
+        assert_eq!(
+            load("rust file=downtown_include_test.rs lines=2-3"),
+            Ok("    // #region body\n    run();".to_string())
+        );
+        assert_eq!(
+            load("file=downtown_include_test.rs lines=5-"),
+            Ok("}".to_string())
+        );
+        assert_eq!(
+            load("file=downtown_include_test.rs region=body"),
+            Ok("    run();".to_string())
+        );
+        assert_eq!(
+            load("file=downtown_include_test.rs lines=4-9"),
+            Err(
+                "lines 4-9 are out of range of 'downtown_include_test.rs' with 5 lines".to_string()
+            )
+        );
+        assert!(load("file=downtown_include_test.rs region=tail")
+            .is_err_and(|err| err == "region 'tail' not found in 'downtown_include_test.rs'"));
+        assert!(load("file=missing.rs")
+            .is_err_and(|err| err.starts_with("can't include 'missing.rs': ")));
+    }
+
+    #[test]
+    fn test_render_lines() {
+        let info = CodeInfo::parse("c {2} linenos");
+        let html = "<span class=\"code-comment\">/* a\nb */</span> x\ny";
+
+        assert_eq!(
+            info.render_lines(html),
+            "<span class=\"code-line\"><span class=\"code-line-number\">1</span><span class=\"code-comment\">/* a</span></span>\n\
+             <span class=\"code-line code-line-highlighted\"><span class=\"code-line-number\">2</span><span class=\"code-comment\">b */</span> x</span>\n\
+             <span class=\"code-line\"><span class=\"code-line-number\">3</span>y</span>"
+        );
+        assert_eq!(CodeInfo::parse("c").render_lines(html), html);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::backticks::{count_backticks, find_closing_backticks};
+
+// Removes Obsidian `%% comments %%` and, optionally, `<!-- html comments -->`. Both can span
+// several lines, the text after such a comment continues the line where it started. Fenced code,
+// math blocks and code spans are left untouched
+pub(crate) fn strip_comments(markdown: &str, strip_html: bool) -> String {
+    let mut res = String::with_capacity(markdown.len());
+    // closing marker of a comment which is not finished yet
+    let mut comment_end: Option<&'static str> = None;
+    // opening sequence of a fenced block we are inside of
+    let mut fence: Option<&'static str> = None;
+    // text of the line where an unfinished comment started
+    let mut comment_start: Option<String> = None;
+
+    for line in markdown.lines() {
+        if comment_end.is_none() {
+            let trimmed = line.trim_start();
+
+            if let Some(f) = fence {
+                if trimmed.starts_with(f) {
+                    fence = None;
+                }
+                res.push_str(line);
+                res.push('\n');
+                continue;
+            }
+
+            if trimmed.starts_with("```") {
+                fence = Some("```");
+            } else if trimmed.starts_with("$$") && !(trimmed.len() > 2 && trimmed.ends_with("$$")) {
+                fence = Some("$$");
+            }
+
+            if fence.is_some() {
+                res.push_str(line);
+                res.push('\n');
+                continue;
+            }
+        }
+
+        let inside_comment = comment_start.is_some();
+        let stripped = comment_start.take().unwrap_or_default()
+            + &strip_line(line, &mut comment_end, strip_html);
+
+        if comment_end.is_some() {
+            comment_start = Some(stripped);
+            continue;
+        }
+
+        // a line which consisted of comments only vanishes so it doesn't break lists or quotes
+        if stripped.trim().is_empty() && (inside_comment || !line.trim().is_empty()) {
+            continue;
+        }
+
+        res.push_str(stripped.trim_end());
+        res.push('\n');
+    }
+
+    // an unclosed comment lasts up to the end of the document
+    if let Some(stripped) = comment_start.filter(|x| !x.trim().is_empty()) {
+        res.push_str(stripped.trim_end());
+        res.push('\n');
+    }
+
+    res
+}
+
+fn strip_line(line: &str, comment_end: &mut Option<&'static str>, strip_html: bool) -> String {
+    let mut res = String::with_capacity(line.len());
+    let mut i = 0;
+
+    while i < line.len() {
+        let rest = &line[i..];
+
+        if let Some(end) = *comment_end {
+            match rest.find(end) {
+                Some(pos) => {
+                    i += pos + end.len();
+                    *comment_end = None;
+                    continue;
+                }
+                None => break,
+            }
+        }
+
+        if rest.starts_with('`') {
+            let ticks = count_backticks(rest);
+            let len = match find_closing_backticks(&rest[ticks..], ticks) {
+                Some(end) => ticks + end + ticks,
+                None => ticks,
+            };
+            res.push_str(&rest[..len]);
+            i += len;
+        } else if rest.starts_with("%%") {
+            *comment_end = Some("%%");
+            i += 2;
+        } else if strip_html && rest.starts_with("<!--") {
+            *comment_end = Some("-->");
+            i += 4;
+        } else {
+            let c = rest.chars().next().unwrap();
+            res.push(c);
+            i += c.len_utf8();
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_comments() {
+        let markdown = r#"## In Work <!-- id: 0 -->
+Visible %%hidden%% text `%%code%%`
+- item
+%%
+whole
+block
+%%
+- item <!-- multi
+line -->end
+```cpp
+printf("%%d"); // <!-- kept -->
+```"#;
+
+        assert_eq!(
+            strip_comments(markdown, true),
+            r#"## In Work
+Visible  text `%%code%%`
+- item
+- item end
+```cpp
+printf("%%d"); // <!-- kept -->
+```
+"#
+        );
+
+        assert_eq!(
+            strip_comments("a <!-- b --> %%c%%", false),
+            "a <!-- b -->\n"
+        );
+    }
+}
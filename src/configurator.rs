@@ -1,6 +1,136 @@
+use crate::attributes::Attributes;
+use crate::code_highlighter::HighlightMarkup;
+use crate::code_info::CodeInfo;
+use crate::emoji::expand_shortcodes;
+use crate::html::{encode_url, escape_attribute, escape_text, strip_tags};
+use crate::languages::{resolve_user_bases, Language};
+use crate::sanitizer::{SafeMode, Sanitizer};
+use crate::terms::{
+    anchor, link_first_occurrences, mark_term, parse_glossary, replace_terms, strip_term_markers,
+};
+use crate::themes::find_theme;
 use crate::toml_parser::TomlDoc;
-use crate::utils::StrUtils;
+use crate::typography::{primary_lang, smarten, QuoteStyle};
 use crate::Level;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Value for a template placeholder. It's escaped depending on where the placeholder is: inside a
+// tag (`alt="{caption}"`) or in an element content (`<figcaption>{caption}</figcaption>`)
+#[derive(Clone, Copy)]
+pub(crate) enum Fragment<'a> {
+    // generated markup; only its text is kept inside attributes
+    Html(&'a str),
+    // plain text
+    Raw(&'a str),
+    // link destination
+    Url(&'a str),
+    // rendered attribute list, escaped already
+    Attributes(&'a str),
+}
+
+impl Fragment<'_> {
+    fn render(&self, inside_tag: bool) -> String {
+        match (self, inside_tag) {
+            (Fragment::Html(html), false) => html.to_string(),
+            (Fragment::Html(html), true) => strip_term_markers(&strip_tags(html))
+                .replace('"', "&quot;")
+                .replace('\'', "&#39;"),
+            (Fragment::Raw(text), false) => escape_text(text),
+            (Fragment::Raw(text), true) => escape_attribute(text),
+            (Fragment::Url(url), _) => escape_attribute(&encode_url(url)),
+            (Fragment::Attributes(attributes), _) => attributes.to_string(),
+        }
+    }
+}
+
+fn is_inside_tag(template_prefix: &str) -> bool {
+    match (template_prefix.rfind('<'), template_prefix.rfind('>')) {
+        (Some(open), Some(close)) => open > close,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+// Available in every template, empty for elements without an attribute list
+const ATTRIBUTE_PLACEHOLDERS: [&str; 3] = ["{attrs}", "{class}", "{id}"];
+
+// Substitutes all placeholders in one pass, so values are never scanned for other placeholders
+pub(crate) fn render_template(template: &str, values: &[(&str, Fragment)]) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut i = 0;
+
+    while let Some(pos) = template[i..].find('{') {
+        let start = i + pos;
+        res.push_str(&template[i..start]);
+
+        if let Some((placeholder, value)) = values
+            .iter()
+            .find(|(placeholder, _)| template[start..].starts_with(placeholder))
+        {
+            res += &value.render(is_inside_tag(&template[..start]));
+            i = start + placeholder.len();
+        } else if let Some(placeholder) = ATTRIBUTE_PLACEHOLDERS
+            .iter()
+            .find(|placeholder| template[start..].starts_with(*placeholder))
+        {
+            i = start + placeholder.len();
+        } else {
+            res.push('{');
+            i = start + 1;
+        }
+    }
+
+    res.push_str(&template[i..]);
+    res
+}
+
+fn render_template_with_attributes(
+    template: &str,
+    values: &[(&str, Fragment)],
+    attributes: &Attributes,
+) -> String {
+    let attrs = attributes.render();
+    let class = attributes.classes.join(" ");
+
+    let mut values = values.to_vec();
+    values.extend([
+        ("{attrs}", Fragment::Attributes(&attrs)),
+        ("{class}", Fragment::Raw(&class)),
+        ("{id}", Fragment::Raw(&attributes.id)),
+    ]);
+
+    render_template(template, &values)
+}
+
+// Markup of highlighted code from the `[highlight]` section. A template without `{text}` would
+// leave its tag unclosed, so it's reported and the default markup is used
+pub(crate) fn parse_highlight(doc: &TomlDoc, errors: &mut Vec<String>) -> Option<HighlightMarkup> {
+    let mut templates = doc.table("highlight")?.entries.clone();
+    let inline_theme = templates.remove("inline-theme").and_then(|name| {
+        let theme = find_theme(&name);
+        if theme.is_none() {
+            errors.push(format!("unknown highlight theme '{name}'"));
+        }
+        theme
+    });
+    templates.remove("word-diff");
+    let mut invalid = templates
+        .iter()
+        .filter(|(_, template)| !template.is_empty() && !template.contains("{text}"))
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    invalid.sort();
+    for name in invalid {
+        templates.remove(&name);
+        errors.push(format!(
+            "highlight template '{name}' has no {{text}} placeholder"
+        ));
+    }
+    let word_diff = doc.get_bool("highlight", "word-diff").unwrap_or(false);
+    Some(HighlightMarkup::new(templates, inline_theme, word_diff))
+}
 
 #[derive(Clone)]
 pub(crate) struct Configurator {
@@ -11,9 +141,21 @@ pub(crate) struct Configurator {
     link: String,
     latex: String,
     code: String,
+    // class of a code block, rendered into `{language}` if the language is known
+    code_language: String,
     code_inline: String,
     blockquote: String,
     horizontal_line: String,
+    definition_list: String,
+    definition_term: String,
+    definition: String,
+    container: String,
+    // title line of a container, rendered into `{heading}` if the title is given
+    container_title: String,
+    // per-name container templates from the `[containers]` section
+    containers: HashMap<String, String>,
+    details: String,
+    abbreviation: String,
     paragraph: String,
     bold: String,
     italic: String,
@@ -27,6 +169,41 @@ pub(crate) struct Configurator {
     header5: Option<String>,
     header6: Option<String>,
     error: String,
+
+    emoji: bool,
+    emoji_aliases: HashMap<String, String>,
+
+    smart_punctuation: bool,
+    non_breaking_spaces: bool,
+    quote_styles: HashMap<String, String>,
+
+    // document language taken from the `lang` frontmatter key
+    pub(crate) lang: String,
+
+    // abbreviations defined in the document: term and its title
+    pub(crate) abbreviations: Vec<(String, String)>,
+
+    glossary_terms: Vec<String>,
+    // glossary page url, `{anchor}` is replaced with a term slug
+    glossary_url: String,
+
+    // directory of the Markdown file, `file=` code blocks are relative to it
+    pub(crate) source_dir: PathBuf,
+
+    // language definitions from the `[languages]` section
+    pub(crate) languages: Arc<Vec<Language>>,
+    // span markup of highlighted code from the `[highlight]` section
+    pub(crate) highlight: HighlightMarkup,
+
+    sanitizer: Sanitizer,
+
+    pub(crate) strip_html_comments: bool,
+
+    // guess languages of code blocks without one
+    pub(crate) detect_languages: bool,
+
+    // problems met while loading files of the configuration, shown at the top of the page
+    pub(crate) errors: Vec<String>,
 }
 
 impl Default for Configurator {
@@ -34,19 +211,29 @@ impl Default for Configurator {
         Self {
             prologue: "<html>\n<body>\n".to_string(),
             epilogue: "\n</body>\n</html>".to_string(),
-            image: r#"<img src="{src}" alt="{caption}">"#.to_string(),
-            link: r#"<a href="{src}">{caption}</a>"#.to_string(),
+            image: r#"<img src="{src}" alt="{caption}"{attrs}>"#.to_string(),
+            link: r#"<a href="{src}"{attrs}>{caption}</a>"#.to_string(),
             latex: r#"<p class="latex">{text}</p>"#.to_string(),
-            code: r#"<pre><code class="language-{lang}">{text}</code></pre>"#.to_string(),
+            code: r#"<pre{attrs}><code{language}>{text}</code></pre>"#.to_string(),
+            code_language: r#" class="language-{lang}""#.to_string(),
             code_inline: r#"<code>{text}</code>"#.to_string(),
             blockquote: r#"<blockquote>{text}</blockquote>"#.to_string(),
             horizontal_line: "<hr>".to_string(),
-            paragraph: "<p>{text}</p>".to_string(),
+            definition_list: "<dl>\n{text}\n</dl>".to_string(),
+            definition_term: "\t<dt>{text}</dt>".to_string(),
+            definition: "\t<dd>{text}</dd>".to_string(),
+            container: "<div class=\"{name}\">\n{heading}{text}\n</div>".to_string(),
+            container_title: "<p class=\"container-title\">{title}</p>\n".to_string(),
+            containers: HashMap::new(),
+            details: "<details{attrs}>\n<summary>{summary}</summary>\n{text}\n</details>"
+                .to_string(),
+            abbreviation: r#"<abbr title="{title}">{text}</abbr>"#.to_string(),
+            paragraph: "<p{attrs}>{text}</p>".to_string(),
             bold: "<b>{text}</b>".to_string(),
             italic: "<i>{text}</i>".to_string(),
             italic_bold: "<b><i>{text}</i></b>".to_string(),
             strikethrough: "<s>{text}</s>".to_string(),
-            header: "<h{level}>{text}</h{level}>".to_string(),
+            header: "<h{level}{attrs}>{text}</h{level}>".to_string(),
             header1: None,
             header2: None,
             header3: None,
@@ -54,15 +241,33 @@ impl Default for Configurator {
             header5: None,
             header6: None,
             error: r#"<div class="parse-error">{text}</div>"#.to_string(),
+            emoji: false,
+            emoji_aliases: HashMap::new(),
+            smart_punctuation: false,
+            non_breaking_spaces: false,
+            quote_styles: HashMap::new(),
+            lang: String::new(),
+            abbreviations: vec![],
+            glossary_terms: vec![],
+            glossary_url: "#{anchor}".to_string(),
+            source_dir: PathBuf::new(),
+            languages: Arc::new(vec![]),
+            highlight: HighlightMarkup::default(),
+            sanitizer: Sanitizer::default(),
+            strip_html_comments: false,
+            detect_languages: false,
+            errors: vec![],
         }
     }
 }
 
 impl Configurator {
-    pub fn new(config_toml: String) -> Self {
+    // Files named in the configuration are relative to `config_dir`
+    pub fn new(config_toml: String, config_dir: &Path) -> Self {
         let default_config = Self::default();
 
         let doc = TomlDoc::new(&config_toml);
+        let mut errors = vec![];
 
         Self {
             prologue: doc
@@ -89,6 +294,10 @@ impl Configurator {
                 .get("tags", "code")
                 .unwrap_or(&default_config.code)
                 .clone(),
+            code_language: doc
+                .get("tags", "code-language")
+                .unwrap_or(&default_config.code_language)
+                .clone(),
             code_inline: doc
                 .get("tags", "code-inline")
                 .unwrap_or(&default_config.code_inline)
@@ -101,6 +310,38 @@ impl Configurator {
                 .get("tags", "horizontal-line")
                 .unwrap_or(&default_config.horizontal_line)
                 .clone(),
+            definition_list: doc
+                .get("tags", "dl")
+                .unwrap_or(&default_config.definition_list)
+                .clone(),
+            definition_term: doc
+                .get("tags", "dt")
+                .unwrap_or(&default_config.definition_term)
+                .clone(),
+            definition: doc
+                .get("tags", "dd")
+                .unwrap_or(&default_config.definition)
+                .clone(),
+            container: doc
+                .get("tags", "container")
+                .unwrap_or(&default_config.container)
+                .clone(),
+            container_title: doc
+                .get("tags", "container-title")
+                .unwrap_or(&default_config.container_title)
+                .clone(),
+            containers: doc
+                .table("containers")
+                .map(|table| table.entries.clone())
+                .unwrap_or(default_config.containers),
+            details: doc
+                .get("tags", "details")
+                .unwrap_or(&default_config.details)
+                .clone(),
+            abbreviation: doc
+                .get("tags", "abbreviation")
+                .unwrap_or(&default_config.abbreviation)
+                .clone(),
             paragraph: doc
                 .get("tags", "paragraph")
                 .unwrap_or(&default_config.paragraph)
@@ -153,18 +394,123 @@ impl Configurator {
                 .get("tags", "error")
                 .unwrap_or(&default_config.error)
                 .clone(),
+            emoji: doc
+                .get_bool("options", "emoji")
+                .unwrap_or(default_config.emoji),
+            emoji_aliases: doc
+                .table("emoji")
+                .map(|table| table.entries.clone())
+                .unwrap_or(default_config.emoji_aliases),
+            smart_punctuation: doc
+                .get_bool("options", "smart-punctuation")
+                .unwrap_or(default_config.smart_punctuation),
+            non_breaking_spaces: doc
+                .get_bool("options", "non-breaking-spaces")
+                .unwrap_or(default_config.non_breaking_spaces),
+            quote_styles: doc
+                .table("quotes")
+                .map(|table| table.entries.clone())
+                .unwrap_or(default_config.quote_styles),
+            lang: default_config.lang,
+            abbreviations: default_config.abbreviations,
+            glossary_terms: doc
+                .get("glossary", "file")
+                .map(
+                    |path| match std::fs::read_to_string(config_dir.join(path)) {
+                        Ok(glossary) => parse_glossary(&glossary),
+                        Err(err) => {
+                            errors.push(format!("can't read glossary file '{path}': {err}"));
+                            vec![]
+                        }
+                    },
+                )
+                .unwrap_or(default_config.glossary_terms),
+            glossary_url: doc
+                .get("glossary", "url")
+                .unwrap_or(&default_config.glossary_url)
+                .clone(),
+            source_dir: default_config.source_dir,
+            languages: doc
+                .table("languages")
+                .map(|table| {
+                    let mut languages = table
+                        .entries
+                        .iter()
+                        .filter_map(|(name, path)| {
+                            match std::fs::read_to_string(config_dir.join(path)) {
+                                Ok(definition) => Some(Language::parse(name, &definition)),
+                                Err(err) => {
+                                    errors
+                                        .push(format!("can't read language file '{path}': {err}"));
+                                    None
+                                }
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    languages.sort_by(|a, b| a.name.cmp(&b.name));
+                    resolve_user_bases(&mut languages);
+                    Arc::new(languages)
+                })
+                .unwrap_or(default_config.languages),
+            highlight: parse_highlight(&doc, &mut errors).unwrap_or(default_config.highlight),
+            sanitizer: Sanitizer::new(
+                // a mistyped mode must not turn the protection off
+                doc.get("options", "safe-mode").map_or(SafeMode::Off, |x| {
+                    Sanitizer::parse_mode(x).unwrap_or(SafeMode::Escape)
+                }),
+                doc.get("safe-mode", "tags"),
+                doc.get("safe-mode", "attributes"),
+                doc.get("safe-mode", "schemes"),
+            ),
+            strip_html_comments: doc
+                .get_bool("options", "strip-html-comments")
+                .unwrap_or(default_config.strip_html_comments),
+            detect_languages: doc
+                .get_bool("options", "detect-languages")
+                .unwrap_or(default_config.detect_languages),
+            errors,
         }
     }
 
     pub fn frame_page(&self, title: &str, page: String) -> String {
-        self.prologue.better_replace("{title}", title) + &page + &self.epilogue
+        let errors = self
+            .errors
+            .iter()
+            .map(|err| self.process_error(&escape_text(err)) + "\n")
+            .collect::<String>();
+
+        render_template(&self.prologue, &[("{title}", Fragment::Raw(title))])
+            + &errors
+            + &page
+            + &self.epilogue
     }
 
-    pub fn process_paragraph(&self, text: &str) -> String {
-        self.paragraph.better_replace("{text}", text)
+    pub fn process_paragraph(&self, text: &str, attributes: &Attributes) -> String {
+        let attributes = self.sanitizer.filter_attributes(attributes);
+        render_template_with_attributes(
+            &self.paragraph,
+            &[("{text}", Fragment::Html(text))],
+            &attributes,
+        )
     }
 
-    pub fn process_header(&self, level: Level, text: &str) -> String {
+    // Paragraph of a tight list item is not wrapped, unless it has an attribute list
+    pub fn process_tight_paragraph(&self, text: &str, attributes: &Attributes) -> String {
+        if *attributes == Attributes::default() {
+            text.to_string()
+        } else {
+            self.process_paragraph(text, attributes)
+        }
+    }
+
+    pub fn process_header(&self, level: Level, text: &str, attributes: &Attributes) -> String {
+        let attributes = self.sanitizer.filter_attributes(attributes);
+        let level_str = level.to_string();
+        let values = [
+            ("{text}", Fragment::Html(text)),
+            ("{level}", Fragment::Raw(&level_str)),
+        ];
+
         for (l, h) in [
             (1, &self.header1),
             (2, &self.header2),
@@ -175,69 +521,260 @@ impl Configurator {
         ] {
             if let Some(h) = h {
                 if level == l {
-                    return h
-                        .better_replace("{text}", text)
-                        .better_replace("{level}", &level.to_string());
+                    return render_template_with_attributes(h, &values, &attributes);
                 }
             }
         }
 
-        self.header
-            .better_replace("{text}", text)
-            .better_replace("{level}", &level.to_string())
+        render_template_with_attributes(&self.header, &values, &attributes)
     }
 
     pub fn process_blockquote(&self, text: &str) -> String {
-        self.blockquote.better_replace("{text}", text)
+        render_template(&self.blockquote, &[("{text}", Fragment::Html(text))])
     }
 
     pub fn process_horizontal_line(&self) -> String {
-        self.horizontal_line.clone()
+        render_template(&self.horizontal_line, &[])
+    }
+
+    pub fn process_definition_list(&self, text: &str) -> String {
+        render_template(&self.definition_list, &[("{text}", Fragment::Html(text))])
+    }
+
+    pub fn process_definition_term(&self, text: &str) -> String {
+        render_template(&self.definition_term, &[("{text}", Fragment::Html(text))])
     }
 
-    pub fn process_image(&self, src: &str, caption: &str) -> String {
-        self.image
-            .better_replace("{caption}", caption)
-            .better_replace("{src}", src)
+    pub fn process_definition(&self, text: &str) -> String {
+        render_template(&self.definition, &[("{text}", Fragment::Html(text))])
     }
 
-    pub fn process_link(&self, src: &str, caption: &str) -> String {
-        self.link
-            .better_replace("{caption}", caption)
-            .better_replace("{src}", src)
+    pub fn process_image(
+        &self,
+        src: &str,
+        caption: &str,
+        title: &str,
+        attributes: &Attributes,
+    ) -> String {
+        if !self.sanitizer.is_url_allowed(src) {
+            return self.process_error(caption);
+        }
+
+        render_template_with_attributes(
+            &self.image,
+            &[
+                ("{caption}", Fragment::Html(caption)),
+                ("{src}", Fragment::Url(src)),
+                ("{title}", Fragment::Raw(title)),
+            ],
+            &self.sanitizer.filter_attributes(attributes),
+        )
+    }
+
+    pub fn process_link(
+        &self,
+        src: &str,
+        caption: &str,
+        title: &str,
+        attributes: &Attributes,
+    ) -> String {
+        if !self.sanitizer.is_url_allowed(src) {
+            return caption.to_string();
+        }
+
+        render_template_with_attributes(
+            &self.link,
+            &[
+                ("{caption}", Fragment::Html(caption)),
+                ("{src}", Fragment::Url(src)),
+                ("{title}", Fragment::Raw(title)),
+            ],
+            &self.sanitizer.filter_attributes(attributes),
+        )
     }
 
     pub fn process_latex(&self, text: &str) -> String {
-        self.latex.better_replace("{text}", text)
+        render_template(&self.latex, &[("{text}", Fragment::Html(text))])
     }
 
-    pub fn process_code(&self, lang: &str, text: &str) -> String {
-        self.code
-            .better_replace("{lang}", lang)
-            .better_replace("{text}", text)
+    pub fn process_code(
+        &self,
+        lang: &str,
+        text: &str,
+        title: &str,
+        attributes: &Attributes,
+    ) -> String {
+        let language = if lang.is_empty() {
+            String::new()
+        } else {
+            render_template(&self.code_language, &[("{lang}", Fragment::Raw(lang))])
+        };
+
+        render_template_with_attributes(
+            &self.code,
+            &[
+                ("{lang}", Fragment::Raw(lang)),
+                ("{language}", Fragment::Attributes(&language)),
+                ("{text}", Fragment::Html(text)),
+                ("{title}", Fragment::Raw(title)),
+            ],
+            &self.sanitizer.filter_attributes(attributes),
+        )
     }
 
     pub fn process_code_inline(&self, text: &str) -> String {
-        self.code_inline.better_replace("{text}", text)
+        render_template(&self.code_inline, &[("{text}", Fragment::Html(text))])
     }
 
     pub fn process_bold(&self, text: &str) -> String {
-        self.bold.better_replace("{text}", text)
+        render_template(&self.bold, &[("{text}", Fragment::Html(text))])
     }
 
     pub fn process_italic(&self, text: &str) -> String {
-        self.italic.better_replace("{text}", text)
+        render_template(&self.italic, &[("{text}", Fragment::Html(text))])
     }
 
     pub fn process_italic_bold(&self, text: &str) -> String {
-        self.italic_bold.better_replace("{text}", text)
+        render_template(&self.italic_bold, &[("{text}", Fragment::Html(text))])
     }
 
     pub fn process_strikethrough(&self, text: &str) -> String {
-        self.strikethrough.better_replace("{text}", text)
+        render_template(&self.strikethrough, &[("{text}", Fragment::Html(text))])
+    }
+
+    pub fn process_container(&self, name: &str, title: &str, text: &str) -> String {
+        let template = self.containers.get(name).unwrap_or(&self.container);
+        let values = [
+            ("{name}", Fragment::Raw(name)),
+            ("{title}", Fragment::Html(title)),
+        ];
+        let heading = if title.is_empty() {
+            String::new()
+        } else {
+            render_template(&self.container_title, &values)
+        };
+
+        render_template(
+            template,
+            &[
+                values[0],
+                values[1],
+                ("{heading}", Fragment::Html(&heading)),
+                ("{text}", Fragment::Html(text)),
+            ],
+        )
+    }
+
+    pub fn process_details(&self, summary: &str, text: &str, attributes: &Attributes) -> String {
+        render_template_with_attributes(
+            &self.details,
+            &[
+                ("{summary}", Fragment::Html(summary)),
+                ("{text}", Fragment::Html(text)),
+            ],
+            &self.sanitizer.filter_attributes(attributes),
+        )
+    }
+
+    // Wraps every occurrence of defined abbreviations. `stash` protects the markup from the
+    // following inline passes
+    pub(crate) fn process_abbreviations(
+        &self,
+        text: &str,
+        mut stash: impl FnMut(String) -> String,
+    ) -> String {
+        let terms = self
+            .abbreviations
+            .iter()
+            .map(|(term, _)| escape_text(term))
+            .collect::<Vec<_>>();
+
+        replace_terms(text, &terms, false, |i, text| {
+            stash(render_template(
+                &self.abbreviation,
+                &[
+                    ("{text}", Fragment::Html(text)),
+                    ("{title}", Fragment::Raw(&self.abbreviations[i].1)),
+                ],
+            ))
+        })
+    }
+
+    pub(crate) fn mark_glossary_terms(&self, text: &str) -> String {
+        let terms = self
+            .glossary_terms
+            .iter()
+            .map(|term| escape_text(term))
+            .collect::<Vec<_>>();
+
+        replace_terms(text, &terms, true, mark_term)
+    }
+
+    // Links the first occurrence of every glossary term in a document
+    pub(crate) fn link_glossary_terms(&self, html: &str) -> String {
+        let attributes = Attributes {
+            classes: vec!["glossary".to_string()],
+            ..Default::default()
+        };
+
+        link_first_occurrences(html, |i, text| {
+            let url = self
+                .glossary_url
+                .replace("{anchor}", &anchor(&self.glossary_terms[i]));
+            self.process_link(&url, text, "", &attributes)
+        })
+    }
+
+    // Untrusted documents must not read files from disk
+    pub(crate) fn include_file(&self, info: &CodeInfo) -> Result<String, String> {
+        if self.sanitizer.mode != SafeMode::Off {
+            return Err(format!(
+                "can't include '{}': file includes are disabled in safe mode",
+                info.file
+            ));
+        }
+
+        info.load_file(&self.source_dir)
     }
 
     pub fn process_error(&self, text: &str) -> String {
-        self.error.better_replace("{text}", text)
+        render_template(&self.error, &[("{text}", Fragment::Html(text))])
+    }
+
+    pub fn process_raw_html(&self, html: &str) -> String {
+        match self.sanitizer.mode {
+            SafeMode::Escape => {
+                self.process_paragraph(&self.sanitizer.sanitize(html), &Attributes::default())
+            }
+            _ => self.sanitizer.sanitize(html),
+        }
+    }
+
+    // Tags written inside a paragraph
+    pub fn process_inline_html(&self, html: &str) -> String {
+        self.sanitizer.sanitize(html)
+    }
+
+    pub fn process_typography(&self, text: &str) -> String {
+        if !self.smart_punctuation {
+            return text.to_string();
+        }
+
+        let lang = primary_lang(&self.lang);
+        let quotes = self
+            .quote_styles
+            .get(&lang)
+            .and_then(|style| QuoteStyle::parse(style))
+            .unwrap_or_else(|| QuoteStyle::builtin(&lang));
+
+        smarten(text, &quotes, &lang, self.non_breaking_spaces)
+    }
+
+    pub fn process_emoji(&self, text: &str) -> String {
+        if self.emoji {
+            expand_shortcodes(text, &self.emoji_aliases)
+        } else {
+            text.to_string()
+        }
     }
 }
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+// Subset of GitHub/Slack shortcodes most commonly found in imported notes
+const EMOJI: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("alarm_clock", "⏰"),
+    ("angry", "😠"),
+    ("arrow_down", "⬇️"),
+    ("arrow_left", "⬅️"),
+    ("arrow_right", "➡️"),
+    ("arrow_up", "⬆️"),
+    ("art", "🎨"),
+    ("baby", "👶"),
+    ("beer", "🍺"),
+    ("bell", "🔔"),
+    ("blush", "😊"),
+    ("bomb", "💣"),
+    ("book", "📖"),
+    ("books", "📚"),
+    ("boom", "💥"),
+    ("brain", "🧠"),
+    ("broken_heart", "💔"),
+    ("bug", "🐛"),
+    ("bulb", "💡"),
+    ("calendar", "📆"),
+    ("camera", "📷"),
+    ("cat", "🐱"),
+    ("chart_with_upwards_trend", "📈"),
+    ("chart_with_downwards_trend", "📉"),
+    ("check", "✔️"),
+    ("clap", "👏"),
+    ("clipboard", "📋"),
+    ("coffee", "☕"),
+    ("computer", "💻"),
+    ("confused", "😕"),
+    ("construction", "🚧"),
+    ("cool", "🆒"),
+    ("cry", "😢"),
+    ("crystal_ball", "🔮"),
+    ("dart", "🎯"),
+    ("disappointed", "😞"),
+    ("dog", "🐶"),
+    ("dragon", "🐉"),
+    ("eyes", "👀"),
+    ("facepalm", "🤦"),
+    ("fire", "🔥"),
+    ("flushed", "😳"),
+    ("gem", "💎"),
+    ("ghost", "👻"),
+    ("gift", "🎁"),
+    ("grin", "😁"),
+    ("grinning", "😀"),
+    ("hammer", "🔨"),
+    ("hammer_and_wrench", "🛠️"),
+    ("hand", "✋"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("heavy_check_mark", "✔️"),
+    ("heavy_minus_sign", "➖"),
+    ("heavy_plus_sign", "➕"),
+    ("hourglass", "⌛"),
+    ("house", "🏠"),
+    ("hugs", "🤗"),
+    ("information_source", "ℹ️"),
+    ("joy", "😂"),
+    ("key", "🔑"),
+    ("laughing", "😆"),
+    ("link", "🔗"),
+    ("lock", "🔒"),
+    ("mag", "🔍"),
+    ("memo", "📝"),
+    ("moneybag", "💰"),
+    ("muscle", "💪"),
+    ("neutral_face", "😐"),
+    ("new", "🆕"),
+    ("no_entry", "⛔"),
+    ("no_entry_sign", "🚫"),
+    ("ok", "🆗"),
+    ("ok_hand", "👌"),
+    ("package", "📦"),
+    ("paperclip", "📎"),
+    ("partying_face", "🥳"),
+    ("pencil", "📝"),
+    ("pencil2", "✏️"),
+    ("point_down", "👇"),
+    ("point_left", "👈"),
+    ("point_right", "👉"),
+    ("point_up", "☝️"),
+    ("pray", "🙏"),
+    ("pushpin", "📌"),
+    ("question", "❓"),
+    ("raised_hands", "🙌"),
+    ("recycle", "♻️"),
+    ("red_circle", "🔴"),
+    ("relaxed", "☺️"),
+    ("relieved", "😌"),
+    ("rocket", "🚀"),
+    ("rofl", "🤣"),
+    ("rotating_light", "🚨"),
+    ("scream", "😱"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("skull", "💀"),
+    ("sleeping", "😴"),
+    ("slightly_smiling_face", "🙂"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("smirk", "😏"),
+    ("snake", "🐍"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("squirrel", "🐿️"),
+    ("star", "⭐"),
+    ("stop_sign", "🛑"),
+    ("stuck_out_tongue", "😛"),
+    ("sunglasses", "😎"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("trophy", "🏆"),
+    ("unlock", "🔓"),
+    ("video_game", "🎮"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("wrench", "🔧"),
+    ("x", "❌"),
+    ("zap", "⚡"),
+];
+
+fn builtin_emoji(name: &str) -> Option<&'static str> {
+    EMOJI.iter().find(|(n, _)| *n == name).map(|(_, e)| *e)
+}
+
+fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '+' || c == '-'
+}
+
+fn resolve(name: &str, aliases: &HashMap<String, String>) -> Option<String> {
+    if let Some(alias) = aliases.get(name) {
+        // alias may point to another shortcode, e.g. `shipit = ':squirrel:'`
+        if let Some(target) = alias
+            .strip_prefix(':')
+            .and_then(|x| x.strip_suffix(':'))
+            .and_then(builtin_emoji)
+        {
+            return Some(target.to_string());
+        }
+        return Some(alias.clone());
+    }
+
+    builtin_emoji(name).map(|x| x.to_string())
+}
+
+// Replaces every known `:shortcode:` with its emoji. Unknown shortcodes are left untouched
+pub(crate) fn expand_shortcodes(text: &str, aliases: &HashMap<String, String>) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(':') {
+        res.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let name_len = after
+            .find(|c: char| !is_shortcode_char(c))
+            .unwrap_or(after.len());
+
+        if name_len > 0 && after[name_len..].starts_with(':') {
+            if let Some(emoji) = resolve(&after[..name_len], aliases) {
+                res.push_str(&emoji);
+                rest = &after[name_len + 1..];
+                continue;
+            }
+        }
+
+        // closing colon may open the next shortcode, so advance only past the current one
+        res.push(':');
+        rest = after;
+    }
+
+    res.push_str(rest);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shortcodes() {
+        let mut aliases = HashMap::new();
+        aliases.insert("shipit".to_string(), ":squirrel:".to_string());
+        aliases.insert("godot".to_string(), "🤖".to_string());
+
+        assert_eq!(
+            expand_shortcodes(":rocket: launch at 10:30:tada:", &aliases),
+            "🚀 launch at 10:30🎉"
+        );
+        assert_eq!(expand_shortcodes(":shipit: :godot:", &aliases), "🐿️ 🤖");
+        assert_eq!(
+            expand_shortcodes(":unknown: stays: as is:", &aliases),
+            ":unknown: stays: as is:"
+        );
+        assert_eq!(expand_shortcodes("::+1:", &aliases), ":👍");
+    }
+}
@@ -13,25 +13,40 @@ pub(crate) struct Frontmatter {
 
 impl Frontmatter {
     // returns parsed frontmatter and a markdown without frontmatter
+    // Frontmatter must be at the very beginning of a document, fenced by `---` lines
     pub(crate) fn load(whole_markdown: &str) -> (Option<Self>, &str) {
-        let mut indices = whole_markdown.match_indices("---").map(|x| x.0).take(2);
-        let start_index = indices.next();
-        let end_index = indices.next();
+        let trimmed = whole_markdown.trim_start();
 
-        if start_index.is_none() || end_index.is_none() {
+        let is_fence = |line: &str| line.trim_end() == "---";
+
+        let first_line_end = trimmed.find('\n').unwrap_or(trimmed.len());
+        if !is_fence(&trimmed[..first_line_end]) {
             return (None, whole_markdown);
         }
 
-        let start_index = start_index.unwrap();
-        let end_index = end_index.unwrap();
+        let body_start = first_line_end;
+        let mut line_start = body_start;
+        let mut end_index = None;
+
+        for line in trimmed[body_start..].split_inclusive('\n') {
+            if line_start > body_start && is_fence(line) {
+                end_index = Some(line_start);
+                break;
+            }
+            line_start += line.len();
+        }
+
+        let Some(end_index) = end_index else {
+            return (None, whole_markdown);
+        };
 
         let mut res = Self {
             vars: HashMap::new(),
         };
 
-        res.parse(whole_markdown[start_index + 3..end_index].trim());
+        res.parse(trimmed[body_start..end_index].trim());
 
-        (Some(res), (whole_markdown[end_index + 3..].trim()))
+        (Some(res), (trimmed[end_index + 3..].trim()))
     }
 
     // returns end index
@@ -171,4 +186,14 @@ areas:
             ]
         );
     }
+
+    #[test]
+    fn test_no_frontmatter() {
+        let markdown = "Text\n\n---\n\nMore text\n\n---";
+
+        let (frontmatter, rest) = Frontmatter::load(markdown);
+
+        assert!(frontmatter.is_none());
+        assert_eq!(rest, markdown);
+    }
 }
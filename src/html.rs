@@ -0,0 +1,232 @@
+// Escapes every `&`, `<` and `>`. Used for code where `&lt;` must be shown literally
+pub(crate) fn escape_text(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+// Same as `escape_text` but keeps entity references like `&nbsp;` or `&#8212;` written by hand
+pub(crate) fn escape_text_keep_entities(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        match c {
+            '&' if entity_len(&text[i..]).is_some() => res.push('&'),
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+pub(crate) fn escape_attribute(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+// Length of an entity reference at the beginning of `s`: `&amp;`, `&#123;` or `&#x1F600;`
+pub(crate) fn entity_len(s: &str) -> Option<usize> {
+    let body = s.strip_prefix('&')?;
+    let end = body.find(';')?;
+    let name = &body[..end];
+
+    let valid = if let Some(hex) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+        (1..=6).contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit())
+    } else if let Some(dec) = name.strip_prefix('#') {
+        (1..=7).contains(&dec.len()) && dec.chars().all(|c| c.is_ascii_digit())
+    } else {
+        (2..=32).contains(&name.len())
+            && name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.chars().all(|c| c.is_ascii_alphanumeric())
+    };
+
+    valid.then_some(end + 2)
+}
+
+// Percent-encodes everything that is not allowed in a URL. Already encoded `%XX` sequences and
+// reserved characters like `?`, `&` or `#` are kept as is
+pub(crate) fn encode_url(url: &str) -> String {
+    const ALLOWED: &str = "-._~:/?#[]@!$&'()*+,;=";
+
+    let bytes = url.as_bytes();
+    let mut res = String::with_capacity(url.len());
+
+    for (i, &b) in bytes.iter().enumerate() {
+        let encoded_sequence = b == b'%'
+            && bytes.len() > i + 2
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit();
+
+        if b.is_ascii_alphanumeric() || ALLOWED.as_bytes().contains(&b) || encoded_sequence {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    res
+}
+
+// Drops all tags leaving only a text content. Entities are kept as is
+pub(crate) fn strip_tags(html: &str) -> String {
+    let mut res = String::with_capacity(html.len());
+    let mut inside_tag = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => inside_tag = true,
+            '>' if inside_tag => inside_tag = false,
+            _ if !inside_tag => res.push(c),
+            _ => {}
+        }
+    }
+
+    res
+}
+
+#[derive(Debug)]
+pub(crate) struct Tag {
+    // lowercased tag name
+    pub(crate) name: String,
+    pub(crate) closing: bool,
+    pub(crate) self_closing: bool,
+    // attribute values are kept as written, i.e. with entities not decoded
+    pub(crate) attributes: Vec<(String, Option<String>)>,
+}
+
+// Parses a tag at the beginning of `s` and returns it with its length in bytes
+pub(crate) fn parse_tag(s: &str) -> Option<(Tag, usize)> {
+    let mut rest = s.strip_prefix('<')?;
+    let closing = rest.starts_with('/');
+    if closing {
+        rest = &rest[1..];
+    }
+
+    if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let name_len = rest
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .unwrap_or(rest.len());
+    let name = rest[..name_len].to_lowercase();
+    rest = &rest[name_len..];
+
+    // attributes must be separated from the name: `<https://url>` is not a tag
+    if !rest.starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/') {
+        return None;
+    }
+
+    let mut tag = Tag {
+        name,
+        closing,
+        self_closing: false,
+        attributes: vec![],
+    };
+
+    loop {
+        rest = rest.trim_start();
+
+        if let Some(after) = rest.strip_prefix("/>") {
+            tag.self_closing = true;
+            return Some((tag, s.len() - after.len()));
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            return Some((tag, s.len() - after.len()));
+        }
+
+        let attr_len = rest
+            .find(|c: char| c.is_whitespace() || "\"'>/=".contains(c))
+            .unwrap_or(rest.len());
+        if attr_len == 0 {
+            return None; // unterminated tag or garbage like `<a ="x">` or `<a / b>`
+        }
+
+        let attr_name = rest[..attr_len].to_lowercase();
+        rest = rest[attr_len..].trim_start();
+
+        let value = if let Some(after_eq) = rest.strip_prefix('=') {
+            let after_eq = after_eq.trim_start();
+            let (value, len) =
+                if let Some(quote) = after_eq.chars().next().filter(|&c| c == '"' || c == '\'') {
+                    let end = after_eq[1..].find(quote)?;
+                    (&after_eq[1..end + 1], end + 2)
+                } else {
+                    let end = after_eq
+                        .find(|c: char| c.is_whitespace() || c == '>')
+                        .unwrap_or(after_eq.len());
+                    (&after_eq[..end], end)
+                };
+            rest = &after_eq[len..];
+            Some(value.to_string())
+        } else {
+            None
+        };
+
+        tag.attributes.push((attr_name, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(escape_text("a &lt; <b>"), "a &amp;lt; &lt;b&gt;");
+        assert_eq!(
+            escape_text_keep_entities("&nbsp;&#8212;&#x1F600; & &x; &;"),
+            "&nbsp;&#8212;&#x1F600; &amp; &amp;x; &amp;;"
+        );
+        assert_eq!(
+            escape_attribute(r#"" onclick='x'"#),
+            "&quot; onclick=&#39;x&#39;"
+        );
+        assert_eq!(
+            encode_url(r#"/wiki/Ёж a"b?x=1&y=%20%zz"#),
+            "/wiki/%D0%81%D0%B6%20a%22b?x=1&y=%20%25zz"
+        );
+        assert_eq!(strip_tags("<b>bold</b> &amp; <i>it</i>"), "bold &amp; it");
+    }
+
+    #[test]
+    fn test_parse_tag() {
+        let input = r#"<IMG src='a b.png' alt="x > y" hidden data-x=1 />rest"#;
+        let (tag, len) = parse_tag(input).unwrap();
+        assert_eq!(&input[len..], "rest");
+        assert_eq!(tag.name, "img");
+        assert!(!tag.closing && tag.self_closing);
+        assert_eq!(
+            tag.attributes,
+            vec![
+                ("src".to_string(), Some("a b.png".to_string())),
+                ("alt".to_string(), Some("x > y".to_string())),
+                ("hidden".to_string(), None),
+                ("data-x".to_string(), Some("1".to_string())),
+            ]
+        );
+
+        let (tag, _) = parse_tag("</div >").unwrap();
+        assert!(tag.closing);
+        assert!(parse_tag("< div>").is_none());
+        assert!(parse_tag("<a href=\"x").is_none());
+    }
+}
@@ -0,0 +1,626 @@
+use crate::toml_parser::TomlDoc;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::OnceLock;
+
+// Built-in language definitions, written in the same format as user ones from `[languages]`
+const BUILTIN_LANGUAGES: [&str; 27] = [
+    include_str!("languages/bash.toml"),
+    include_str!("languages/c.toml"),
+    include_str!("languages/cpp.toml"),
+    include_str!("languages/csharp.toml"),
+    include_str!("languages/css.toml"),
+    include_str!("languages/diff.toml"),
+    include_str!("languages/dockerfile.toml"),
+    include_str!("languages/gdscript.toml"),
+    include_str!("languages/gdshader.toml"),
+    include_str!("languages/glsl.toml"),
+    include_str!("languages/go.toml"),
+    include_str!("languages/hlsl.toml"),
+    include_str!("languages/html.toml"),
+    include_str!("languages/ini.toml"),
+    include_str!("languages/java.toml"),
+    include_str!("languages/javascript.toml"),
+    include_str!("languages/json.toml"),
+    include_str!("languages/jsx.toml"),
+    include_str!("languages/kotlin.toml"),
+    include_str!("languages/lua.toml"),
+    include_str!("languages/python.toml"),
+    include_str!("languages/rust.toml"),
+    include_str!("languages/toml.toml"),
+    include_str!("languages/tsx.toml"),
+    include_str!("languages/typescript.toml"),
+    include_str!("languages/yaml.toml"),
+    include_str!("languages/zig.toml"),
+];
+
+// Syntax of a language for the code highlighter:
+//
+// [language]
+// name = 'rust'
+// aliases = 'rs'
+// keywords = 'fn let mut'
+// types = 'i32 u8'
+// line-comments = '//'
+// block-comments = '/* */'
+// strings = '" ''
+// raw-strings = '`'
+// multiline-strings = '`'
+// char-literals = "'"
+// escape = '\'
+// string-prefixes = 'b'
+// raw-string-prefixes = 'r br'
+// format-string-prefixes = 'f'
+// nested-comments = true
+// number-prefixes = '0x 0o 0b'
+// number-separators = '_'
+// number-suffixes = 'u8 i32 f64'
+// capitalized-types = true
+// macro-suffix = '!'
+// annotations = '@'
+// attributes = '#[ ]'
+// preprocessor = '#'
+// operators = '+ - * / = < > ! & |'
+// punctuation = '( ) [ ] { } ; , .'
+// variable-prefixes = '$ %'
+// variable-chars = '/'
+// variable-specials = '@ ?'
+// interpolated-strings = '"'
+// key-separators = '='
+// key-chars = '- .'
+// sections = '[ ]'
+// tags = 'markup'
+// embedded = 'script:javascript style:css'
+// base = 'javascript'
+// signatures = '=> console.'
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Language {
+    pub(crate) name: String,
+    aliases: Vec<String>,
+    pub(crate) keywords: HashSet<String>,
+    pub(crate) types: HashSet<String>,
+    pub(crate) line_comments: Vec<String>,
+    // pairs of opening and closing markers
+    pub(crate) block_comments: Vec<(String, String)>,
+    // a string ends with the same delimiter it starts with
+    pub(crate) strings: Vec<String>,
+    // delimiters of strings without escapes: `'$HOME'` in Bash
+    pub(crate) raw_strings: Vec<String>,
+    // delimiters of strings which may span lines. Others end at the end of a line if not closed
+    // and don't start right after a word, so `it's` is not a string
+    pub(crate) multiline_strings: Vec<String>,
+    // delimiters of one-character literals: `'a'` is a char, `'a` is a Rust lifetime
+    pub(crate) char_literals: Vec<String>,
+    // escape sequence start in strings: `"a \"quoted\" word"`
+    pub(crate) escape: String,
+    // prefixes of strings, case-insensitive: `b"bytes"`, raw `r"\d+"`, format `f"{x}"`
+    pub(crate) string_prefixes: Vec<String>,
+    pub(crate) raw_string_prefixes: Vec<String>,
+    pub(crate) format_string_prefixes: Vec<String>,
+    // `/* /* */ */` is a single comment
+    pub(crate) nested_comments: bool,
+    pub(crate) number_prefixes: Vec<String>,
+    pub(crate) number_separators: Vec<char>,
+    pub(crate) number_suffixes: Vec<String>,
+    // identifiers starting with a capital letter are types: `Vec`, `HashMap`, but not `MAX_SIZE`
+    pub(crate) capitalized_types: bool,
+    // a word followed by it is a macro call: `println!`
+    pub(crate) macro_suffix: String,
+    // prefixes of annotations and decorators: `@export`
+    pub(crate) annotations: Vec<String>,
+    // pairs of brackets around attributes: `#[derive(Debug)]`, `[[nodiscard]]`
+    pub(crate) attributes: Vec<(String, String)>,
+    // prefixes of preprocessor lines: `#include <vector>`
+    pub(crate) preprocessor: Vec<String>,
+    pub(crate) operators: Vec<char>,
+    pub(crate) punctuation: Vec<char>,
+    // prefixes of variables and node paths: `$HOME`, `$Player/Sprite`
+    pub(crate) variable_prefixes: Vec<String>,
+    // characters allowed inside a variable name besides letters, digits and `_`
+    pub(crate) variable_chars: Vec<char>,
+    // one-character variables after a prefix: `$@`, `$?`
+    pub(crate) variable_specials: Vec<char>,
+    // delimiters of strings with variables inside: `"Hello, $USER"`
+    pub(crate) interpolated_strings: Vec<String>,
+    // config formats: a key is followed by one of them, `key = value` or `key: value`
+    pub(crate) key_separators: Vec<char>,
+    // characters allowed inside a bare key besides letters, digits and `_`
+    pub(crate) key_chars: Vec<char>,
+    // pairs of brackets around section headers at the beginning of a line: `[section]`
+    pub(crate) sections: Vec<(String, String)>,
+    // `markup` for HTML-like languages, `jsx` for elements inside code: `return <div />`
+    pub(crate) tags: String,
+    // languages of tag contents: `<script>` is JavaScript
+    pub(crate) embedded: Vec<(String, String)>,
+    // a language whose definition is extended by this one
+    base: String,
+    // distinctive parts of code used to guess a language of unlabeled code blocks: `std::`
+    signatures: Vec<String>,
+    // the first language of a `base` chain, empty if there is no base
+    family: String,
+}
+
+fn words(s: Option<&String>) -> Vec<String> {
+    s.map_or(vec![], |x| {
+        x.split_whitespace().map(str::to_string).collect()
+    })
+}
+
+fn pairs(s: Option<&String>) -> Vec<(String, String)> {
+    words(s)
+        .chunks_exact(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect()
+}
+
+fn chars(s: Option<&String>) -> Vec<char> {
+    s.map_or(vec![], |x| {
+        x.chars().filter(|c| !c.is_whitespace()).collect()
+    })
+}
+
+impl Language {
+    // Parses a definition file. `name` is used unless the file sets its own
+    pub(crate) fn parse(name: &str, toml: &str) -> Self {
+        let doc = TomlDoc::new(toml);
+        let get = |key| doc.get("language", key);
+
+        Language {
+            name: get("name").map_or(name, |x| x.as_str()).to_lowercase(),
+            aliases: words(get("aliases")),
+            keywords: words(get("keywords")).into_iter().collect(),
+            types: words(get("types")).into_iter().collect(),
+            line_comments: words(get("line-comments")),
+            block_comments: pairs(get("block-comments")),
+            strings: words(get("strings")),
+            raw_strings: words(get("raw-strings")),
+            multiline_strings: words(get("multiline-strings")),
+            char_literals: words(get("char-literals")),
+            escape: get("escape").cloned().unwrap_or_default(),
+            string_prefixes: words(get("string-prefixes")),
+            raw_string_prefixes: words(get("raw-string-prefixes")),
+            format_string_prefixes: words(get("format-string-prefixes")),
+            nested_comments: doc.get_bool("language", "nested-comments").unwrap_or(false),
+            number_prefixes: words(get("number-prefixes")),
+            number_separators: chars(get("number-separators")),
+            number_suffixes: words(get("number-suffixes")),
+            capitalized_types: doc
+                .get_bool("language", "capitalized-types")
+                .unwrap_or(false),
+            macro_suffix: get("macro-suffix").cloned().unwrap_or_default(),
+            annotations: words(get("annotations")),
+            attributes: pairs(get("attributes")),
+            preprocessor: words(get("preprocessor")),
+            operators: chars(get("operators")),
+            punctuation: chars(get("punctuation")),
+            variable_prefixes: words(get("variable-prefixes")),
+            variable_chars: chars(get("variable-chars")),
+            variable_specials: chars(get("variable-specials")),
+            interpolated_strings: words(get("interpolated-strings")),
+            key_separators: chars(get("key-separators")),
+            key_chars: chars(get("key-chars")),
+            sections: pairs(get("sections")),
+            tags: get("tags").cloned().unwrap_or_default(),
+            embedded: words(get("embedded"))
+                .iter()
+                .filter_map(|x| x.split_once(':'))
+                .map(|(tag, lang)| (tag.to_string(), lang.to_string()))
+                .collect(),
+            base: get("base").cloned().unwrap_or_default(),
+            signatures: words(get("signatures")),
+            family: String::new(),
+        }
+    }
+
+    fn is_called(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|x| x.eq_ignore_ascii_case(name))
+    }
+
+    // Takes everything not set in this definition from `base`, keywords and types are merged
+    fn inherit(&mut self, base: &Language) {
+        fn or<T: Clone>(value: &mut Vec<T>, base: &[T]) {
+            if value.is_empty() {
+                *value = base.to_vec();
+            }
+        }
+
+        self.keywords.extend(base.keywords.iter().cloned());
+        self.types.extend(base.types.iter().cloned());
+        or(&mut self.line_comments, &base.line_comments);
+        or(&mut self.block_comments, &base.block_comments);
+        or(&mut self.strings, &base.strings);
+        or(&mut self.raw_strings, &base.raw_strings);
+        or(&mut self.multiline_strings, &base.multiline_strings);
+        or(&mut self.char_literals, &base.char_literals);
+        or(&mut self.string_prefixes, &base.string_prefixes);
+        or(&mut self.raw_string_prefixes, &base.raw_string_prefixes);
+        or(
+            &mut self.format_string_prefixes,
+            &base.format_string_prefixes,
+        );
+        if self.escape.is_empty() {
+            self.escape = base.escape.clone();
+        }
+        self.nested_comments |= base.nested_comments;
+        or(&mut self.number_prefixes, &base.number_prefixes);
+        or(&mut self.number_separators, &base.number_separators);
+        or(&mut self.number_suffixes, &base.number_suffixes);
+        self.capitalized_types |= base.capitalized_types;
+        if self.macro_suffix.is_empty() {
+            self.macro_suffix = base.macro_suffix.clone();
+        }
+        or(&mut self.annotations, &base.annotations);
+        or(&mut self.attributes, &base.attributes);
+        or(&mut self.preprocessor, &base.preprocessor);
+        or(&mut self.operators, &base.operators);
+        or(&mut self.punctuation, &base.punctuation);
+        or(&mut self.variable_prefixes, &base.variable_prefixes);
+        or(&mut self.variable_chars, &base.variable_chars);
+        or(&mut self.variable_specials, &base.variable_specials);
+        or(&mut self.interpolated_strings, &base.interpolated_strings);
+        or(&mut self.key_separators, &base.key_separators);
+        or(&mut self.key_chars, &base.key_chars);
+        or(&mut self.sections, &base.sections);
+        or(&mut self.embedded, &base.embedded);
+        or(&mut self.signatures, &base.signatures);
+        if self.tags.is_empty() {
+            self.tags = base.tags.clone();
+        }
+        self.base = base.base.clone();
+        self.family = base.family().to_string();
+    }
+
+    // Languages of a family share signs, so a sign is as distinctive as the number of families with it
+    fn family(&self) -> &str {
+        if self.family.is_empty() {
+            &self.name
+        } else {
+            &self.family
+        }
+    }
+
+    pub(crate) fn is_keyword(&self, word: &str) -> bool {
+        self.keywords.contains(word)
+    }
+
+    // Built-in types and, if enabled, capitalized identifiers
+    pub(crate) fn is_type(&self, word: &str) -> bool {
+        self.types.contains(word)
+            || (self.capitalized_types
+                && word.starts_with(|c: char| c.is_uppercase())
+                && word.contains(|c: char| c.is_lowercase()))
+    }
+}
+
+// Applies `base` definitions. A base is looked up among `languages` first, then in `known` ones
+fn resolve_bases(languages: &mut [Language], known: &[Language]) {
+    // a base may have a base of its own, a few rounds resolve chains and stop on cycles
+    for _ in 0..4 {
+        for i in 0..languages.len() {
+            if languages[i].base.is_empty() {
+                continue;
+            }
+
+            let base_name = languages[i].base.to_lowercase();
+            let base = languages
+                .iter()
+                .chain(known)
+                .find(|x| x.is_called(&base_name) && x.base.is_empty())
+                .cloned();
+            if let Some(base) = base {
+                languages[i].inherit(&base);
+            }
+        }
+    }
+}
+
+fn builtin_languages() -> &'static [Language] {
+    static LANGUAGES: OnceLock<Vec<Language>> = OnceLock::new();
+    LANGUAGES.get_or_init(|| {
+        let mut languages = BUILTIN_LANGUAGES
+            .iter()
+            .map(|toml| Language::parse("", toml))
+            .collect::<Vec<_>>();
+        resolve_bases(&mut languages, &[]);
+        languages
+    })
+}
+
+pub(crate) fn resolve_user_bases(languages: &mut [Language]) {
+    resolve_bases(languages, builtin_languages());
+}
+
+// Used for unknown languages: words which are keywords or types in several built-in languages,
+// with C-like comments
+fn generic_language() -> &'static Language {
+    static LANGUAGE: OnceLock<Language> = OnceLock::new();
+    LANGUAGE.get_or_init(|| {
+        let mut res = Language::parse(
+            "",
+            "[language]\nline-comments = '//'\nblock-comments = '/* */'\nstrings = '\" ''",
+        );
+
+        let common = |words: fn(&Language) -> &HashSet<String>| {
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for language in builtin_languages() {
+                for word in words(language) {
+                    *counts.entry(word).or_default() += 1;
+                }
+            }
+            counts
+                .into_iter()
+                .filter(|(_, count)| *count >= 3)
+                .map(|(word, _)| word.to_string())
+                .collect::<HashSet<_>>()
+        };
+        res.keywords = common(|x| &x.keywords);
+        res.types = common(|x| &x.types);
+
+        res
+    })
+}
+
+// Looks a language up by its name or alias. User definitions take precedence over built-in ones
+pub(crate) fn find_language<'a>(
+    name: &str,
+    user_languages: &'a [Language],
+) -> Option<&'a Language> {
+    let name = name.to_lowercase();
+    user_languages
+        .iter()
+        .chain(builtin_languages())
+        .find(|x| x.is_called(&name))
+}
+
+pub(crate) fn find_language_or_generic<'a>(
+    name: &str,
+    user_languages: &'a [Language],
+) -> &'a Language {
+    find_language(name, user_languages).unwrap_or_else(|| generic_language())
+}
+
+// Signs of `language` found in `code`: its keywords, types and signatures, keys of config formats,
+// and the comment, preprocessor, section, annotation, attribute, tag and macro syntax
+fn features(code: &str, language: &Language) -> BTreeSet<String> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    // words of comments are not signs: `// the number of items`
+    let mut res = code
+        .lines()
+        .map(|line| {
+            let comment = language
+                .line_comments
+                .iter()
+                .filter_map(|marker| line.find(marker.as_str()))
+                .min();
+            &line[..comment.unwrap_or(line.len())]
+        })
+        .flat_map(|line| line.split(|c: char| !is_word_char(c)))
+        .filter(|x| language.keywords.contains(*x) || language.types.contains(*x))
+        .map(str::to_string)
+        .collect::<BTreeSet<_>>();
+    for signature in &language.signatures {
+        if code.contains(signature.as_str()) {
+            res.insert(format!("signature {signature}"));
+        }
+    }
+
+    for line in code.lines().map(str::trim) {
+        let starts_word = |prefix: &str| {
+            line.strip_prefix(prefix)
+                .is_some_and(|x| x.starts_with(is_word_char))
+        };
+
+        for marker in &language.line_comments {
+            if line.starts_with(marker.as_str()) {
+                res.insert(format!("comment {marker}"));
+            }
+        }
+        for (start, _) in &language.block_comments {
+            if line.starts_with(start.as_str()) {
+                res.insert(format!("comment {start}"));
+            }
+        }
+        for (open, close) in &language.sections {
+            if line.starts_with(open.as_str()) && line.ends_with(close.as_str()) {
+                res.insert(format!("section {open}"));
+            }
+        }
+        for (open, _) in &language.attributes {
+            if line.starts_with(open.as_str()) {
+                res.insert(format!("attribute {open}"));
+            }
+        }
+        for prefix in &language.preprocessor {
+            if starts_word(prefix) {
+                res.insert(format!("preprocessor {prefix}"));
+            }
+        }
+        for prefix in &language.annotations {
+            if starts_word(prefix) {
+                res.insert(format!("annotation {prefix}"));
+            }
+        }
+        for &separator in &language.key_separators {
+            let is_key = line.split_once(separator).is_some_and(|(key, value)| {
+                let key = key.trim().trim_matches('"');
+                !key.is_empty()
+                    && key
+                        .chars()
+                        .all(|c| is_word_char(c) || language.key_chars.contains(&c))
+                    && (separator != ':' || value.is_empty() || value.starts_with(' '))
+            });
+            if is_key {
+                res.insert(format!("key {separator}"));
+            }
+        }
+        if language.tags == "markup" && (starts_word("<") || line.starts_with("</")) {
+            res.insert("markup".to_string());
+        }
+        if !language.macro_suffix.is_empty()
+            && line
+                .match_indices(language.macro_suffix.as_str())
+                .any(|(i, suffix)| {
+                    line[..i].ends_with(is_word_char)
+                        && line[i + suffix.len()..].starts_with(['(', '[', '{'])
+                })
+        {
+            res.insert(format!("macro {}", language.macro_suffix));
+        }
+    }
+
+    res
+}
+
+// Language of the interpreter named in a shebang: `/bin/bash` or `/usr/bin/env -S python3 -u`
+fn shebang_language<'a>(shebang: &str, user_languages: &'a [Language]) -> Option<&'a Language> {
+    let mut words = shebang.split_whitespace();
+    let path = words.next()?;
+    let mut program = match path.strip_prefix('/') {
+        Some(path) => path.rsplit('/').next()?,
+        None if path == "env" => path,
+        None => return None,
+    };
+    if program == "env" {
+        program = words.find(|x| !x.starts_with('-'))?;
+    }
+    find_language(
+        program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'),
+        user_languages,
+    )
+}
+
+// Guesses the language of an unlabeled code block by a shebang, a diff hunk or the signs of
+// languages in the code. A sign shared by several languages weighs less, and the best guess has to
+// be clearly ahead of others
+pub(crate) fn detect_language<'a>(
+    code: &str,
+    user_languages: &'a [Language],
+) -> Option<&'a Language> {
+    const MIN_SCORE: f64 = 1.5;
+    const MIN_LEAD: f64 = 1.25;
+
+    // `#!/usr/bin/env python3` is `python`. Only a path is a shebang, `#![allow(dead_code)]` is
+    // Rust, and an unknown program like `node` leaves the guess to the scores
+    let shebang = code.lines().next().and_then(|x| x.strip_prefix("#!"));
+    if let Some(language) = shebang.and_then(|x| shebang_language(x, user_languages)) {
+        return Some(language);
+    }
+    if code.lines().any(|x| x.starts_with("@@ -")) {
+        return find_language("diff", user_languages);
+    }
+
+    // a built-in language overridden by a user one is skipped
+    let candidates = user_languages
+        .iter()
+        .chain(builtin_languages())
+        .filter(|x| find_language(&x.name, user_languages).is_some_and(|y| std::ptr::eq(*x, y)))
+        .collect::<Vec<_>>();
+    let features = candidates
+        .iter()
+        .map(|x| features(code, x))
+        .collect::<Vec<_>>();
+
+    let mut families: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for (language, features) in candidates.iter().zip(&features) {
+        for feature in features {
+            families
+                .entry(feature)
+                .or_default()
+                .insert(language.family());
+        }
+    }
+    let mut scores = candidates
+        .iter()
+        .zip(&features)
+        .map(|(language, features)| {
+            let score = features
+                .iter()
+                .map(|x| 1.0 / families[x.as_str()].len() as f64)
+                .sum::<f64>();
+            (*language, score)
+        })
+        .collect::<Vec<_>>();
+    // a language extending another one has the same score for the common code, so the base one
+    // wins, then the one without JSX
+    scores.sort_by(|a, b| {
+        b.1.total_cmp(&a.1)
+            .then_with(|| (a.0.family() != a.0.name).cmp(&(b.0.family() != b.0.name)))
+            .then_with(|| a.0.tags.cmp(&b.0.tags))
+    });
+
+    let (best, best_score) = *scores.first()?;
+    let second_score = scores
+        .iter()
+        .map(|x| x.1)
+        .find(|&x| x < best_score - f64::EPSILON)
+        .unwrap_or(0.0);
+    (best_score >= MIN_SCORE && best_score >= MIN_LEAD * second_score).then_some(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let language = Language::parse(
+            "gd",
+            "[language]\naliases = 'gdscript'\nkeywords = '''\nfunc var\nsignal\n'''\n\
+             line-comments = '#'\nblock-comments = '/* */ <!-- -->'\nnumber-separators = '_'",
+        );
+
+        assert_eq!(language.name, "gd");
+        assert!(language.is_called("GDScript"));
+        assert!(language.is_keyword("signal"));
+        assert_eq!(language.line_comments, vec!["#"]);
+        assert_eq!(
+            language.block_comments,
+            vec![
+                ("/*".to_string(), "*/".to_string()),
+                ("<!--".to_string(), "-->".to_string())
+            ]
+        );
+        assert_eq!(language.number_separators, vec!['_']);
+    }
+
+    #[test]
+    fn test_find_language() {
+        let user_languages = vec![Language::parse("python", "[language]\naliases = 'py'")];
+
+        assert_eq!(find_language("rs", &[]).unwrap().name, "rust");
+        assert_eq!(find_language("C++", &[]).unwrap().name, "cpp");
+        assert!(find_language("py", &user_languages)
+            .unwrap()
+            .keywords
+            .is_empty());
+        assert!(find_language("unknown", &[]).is_none());
+        assert!(find_language_or_generic("unknown", &[]).is_keyword("return"));
+        assert!(!find_language_or_generic("unknown", &[]).is_keyword("esac"));
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let detect = |code| detect_language(code, &[]).map(|x| x.name.as_str());
+
+        assert_eq!(
+            detect("fn main() {\n    println!(\"hi\");\n}"),
+            Some("rust")
+        );
+        assert_eq!(detect("def f(x):\n    return x + 1\n"), Some("python"));
+        assert_eq!(
+            detect("const x = require('y');\nconsole.log(x === 1);"),
+            Some("javascript")
+        );
+        assert_eq!(detect("#!/usr/bin/env bash\nls"), Some("bash"));
+        assert_eq!(detect("#!/bin/sh -e\nls"), Some("bash"));
+        assert_eq!(
+            detect("#![allow(dead_code)]\nfn main() {\n    println!(\"hi\");\n}"),
+            Some("rust")
+        );
+        assert_eq!(
+            detect("#!/usr/bin/env node\nconst x = require('y');\nconsole.log(x === 1);"),
+            Some("javascript")
+        );
+        assert_eq!(detect("@@ -1 +1 @@\n-a\n+b"), Some("diff"));
+        assert_eq!(detect("Hello world, this is some output."), None);
+    }
+}
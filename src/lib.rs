@@ -1,18 +1,40 @@
+mod attributes;
+mod backticks;
 mod code_highlighter;
+mod code_info;
+mod comments;
 mod configurator;
+mod emoji;
 mod frontmatter_parser;
+mod html;
+mod languages;
+mod sanitizer;
+mod terms;
+mod themes;
 mod toml_parser;
+mod typography;
 mod utils;
 
+use crate::attributes::Attributes;
+use crate::backticks::{count_backticks, find_closing_backticks};
 use crate::code_highlighter::highlight_code;
+use crate::code_info::CodeInfo;
+use crate::comments::strip_comments;
 use crate::configurator::Configurator;
 use crate::frontmatter_parser::Frontmatter;
+use crate::html::{escape_text, escape_text_keep_entities, parse_tag};
+use crate::languages::detect_language;
+use crate::sanitizer::DROP_WITH_CONTENT;
 use crate::utils::StrUtils;
 use std::cmp::PartialEq;
+use std::io;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+pub use crate::themes::{theme_css, theme_names};
+
 type Block = Arc<str>;
 type BlocksSlice = Arc<[Block]>;
 type ParseUnit = BlocksSlice;
@@ -24,61 +46,262 @@ enum UnitType {
     Header(Level),
     Text,
     List,
+    DefinitionList,
     Image,
     Latex,
     Code,
     Blockquote,
+    Container,
+    Details,
     HorizontalLine,
     LocalLink,
     RawText, // e.x. for html tags
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum HtmlBlockEnd {
+    // line containing the marker is the last line of the block
+    Marker(&'static str),
+    BlankLine,
+}
+
+// Tags which start an html block ending at a blank line, so Markdown between an opening and a
+// closing tag separated by blank lines is processed as usual
+const HTML_BLOCK_TAGS: [&str; 62] = [
+    "address",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "blockquote",
+    "body",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "iframe",
+    "legend",
+    "li",
+    "link",
+    "main",
+    "menu",
+    "menuitem",
+    "nav",
+    "noframes",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "param",
+    "search",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+];
+
+// Start conditions of CommonMark html blocks
+fn html_block_start(line: &str) -> Option<HtmlBlockEnd> {
+    if !line.starts_with('<') {
+        return None;
+    }
+
+    let lowercase = line.to_lowercase();
+
+    for (tag, end) in [
+        ("<pre", "</pre>"),
+        ("<script", "</script>"),
+        ("<style", "</style>"),
+        ("<textarea", "</textarea>"),
+    ] {
+        if let Some(rest) = lowercase.strip_prefix(tag) {
+            if rest.is_empty() || rest.starts_with(|c: char| c.is_whitespace() || c == '>') {
+                return Some(HtmlBlockEnd::Marker(end));
+            }
+        }
+    }
+
+    for (start, end) in [("<!--", "-->"), ("<?", "?>"), ("<![CDATA[", "]]>")] {
+        if line.starts_with(start) {
+            return Some(HtmlBlockEnd::Marker(end));
+        }
+    }
+
+    if line.starts_with("<!") && line[2..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Some(HtmlBlockEnd::Marker(">"));
+    }
+
+    let name = lowercase
+        .trim_start_matches(['<', '/'])
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .next()
+        .unwrap_or_default();
+    if HTML_BLOCK_TAGS.contains(&name) {
+        let rest = &lowercase[lowercase.find(name).unwrap() + name.len()..];
+        if rest.is_empty() || rest.starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        {
+            return Some(HtmlBlockEnd::BlankLine);
+        }
+    }
+
+    // any other complete tag alone on a line
+    if let Some((_, len)) = parse_tag(line) {
+        if line[len..].trim().is_empty() {
+            return Some(HtmlBlockEnd::BlankLine);
+        }
+    }
+
+    None
+}
+
+// `<div class="note">text</div>` alone on a line is a whole block
+fn is_one_line_element(line: &str) -> bool {
+    parse_tag(line).is_some_and(|(tag, _)| {
+        !tag.closing && line.to_lowercase().ends_with(&format!("</{}>", tag.name))
+    })
+}
+
+fn html_block_ends(line: &str, end: HtmlBlockEnd) -> bool {
+    match end {
+        HtmlBlockEnd::Marker(marker) => line.to_lowercase().contains(marker),
+        HtmlBlockEnd::BlankLine => line.trim().is_empty(),
+    }
+}
+
 struct ParseContext {
     parse_units: Vec<ParseUnit>,
     unit_types: Vec<UnitType>,
     title: String,
     #[allow(unused)]
     tags: Vec<String>,
+    lang: String,
+    // `*[HTML]: HyperText Markup Language` definitions
+    abbreviations: Vec<(String, String)>,
 }
 
 pub struct Markdown2Html {
+    source: String,
+    // directory of the Markdown file, code blocks include files relative to it
+    source_dir: Option<PathBuf>,
     parse_context: ParseContext,
     number_of_threads: u8,
     configurator: Configurator,
+    // the document is an item of a tight list, its paragraphs are not wrapped
+    tight: bool,
 }
 
 impl Markdown2Html {
     pub fn new(input: String) -> Markdown2Html {
-        let (frontmatter, markdown) = Frontmatter::load(&input);
-        let markdown: Vec<_> = markdown.lines().map(|x| Arc::from(x.trim_end())).collect();
-
-        let parse_context = Markdown2Html::analyze_input(markdown, frontmatter);
+        let configurator = Configurator::default();
+        let parse_context = Markdown2Html::parse(&input, &configurator);
 
         Markdown2Html {
+            source: input,
+            source_dir: None,
             parse_context,
             number_of_threads: 0,
-            configurator: Default::default(),
+            configurator,
+            tight: false,
         }
     }
 
+    // Files named in `config_toml` are relative to the current directory
     pub fn new_with_config(
         input: String,
         number_of_threads: u8,
         config_toml: Option<String>,
     ) -> Markdown2Html {
-        let (frontmatter, markdown) = Frontmatter::load(&input);
-        let markdown: Vec<_> = markdown.lines().map(|x| Arc::from(x.trim_end())).collect();
+        let configurator = if let Some(config_toml) = config_toml {
+            Configurator::new(config_toml, Path::new(""))
+        } else {
+            Configurator::default()
+        };
+        Markdown2Html::new_with_configurator(input, number_of_threads, configurator)
+    }
+
+    // Files named in the configuration are relative to the configuration file
+    pub fn new_with_config_file(
+        input: String,
+        number_of_threads: u8,
+        config_path: &Path,
+    ) -> io::Result<Markdown2Html> {
+        let config_toml = std::fs::read_to_string(config_path)?;
+        let config_dir = config_path.parent().unwrap_or(Path::new(""));
+        let configurator = Configurator::new(config_toml, config_dir);
+        Ok(Markdown2Html::new_with_configurator(
+            input,
+            number_of_threads,
+            configurator,
+        ))
+    }
 
-        let parse_context = Markdown2Html::analyze_input(markdown, frontmatter);
+    fn new_with_configurator(
+        input: String,
+        number_of_threads: u8,
+        configurator: Configurator,
+    ) -> Markdown2Html {
+        let parse_context = Markdown2Html::parse(&input, &configurator);
 
         Markdown2Html {
+            source: input,
+            source_dir: None,
             parse_context,
             number_of_threads,
-            configurator: if let Some(config_toml) = config_toml {
-                Configurator::new(config_toml)
-            } else {
-                Configurator::default()
-            },
+            configurator,
+            tight: false,
+        }
+    }
+
+    // Parser of blocks nested into a list item or a container. They are a part of the document
+    // which is loaded already, so there is no frontmatter and no comments to strip
+    fn new_nested(input: &str, configurator: &Configurator, tight: bool) -> Markdown2Html {
+        let markdown = input.lines().map(|x| Arc::from(x.trim_end())).collect();
+
+        let mut configurator = configurator.clone();
+        configurator.prologue = String::new();
+        configurator.epilogue = String::new();
+        // reported by the whole document already
+        configurator.errors.clear();
+
+        Markdown2Html {
+            source: input.to_string(),
+            source_dir: None,
+            parse_context: Markdown2Html::analyze_input(markdown, None),
+            number_of_threads: 1,
+            configurator,
+            tight,
         }
     }
 
@@ -86,25 +309,58 @@ impl Markdown2Html {
         self.number_of_threads = number_of_threads;
     }
 
+    pub fn set_source_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.source_dir = Some(dir.into());
+    }
+
     pub fn set_configuration(&mut self, toml_file_content: String) {
-        self.configurator = Configurator::new(toml_file_content);
+        self.configurator = Configurator::new(toml_file_content, Path::new(""));
+        // configuration affects preprocessing, so the source is analyzed again
+        self.parse_context = Markdown2Html::parse(&self.source, &self.configurator);
+    }
+
+    fn parse(input: &str, configurator: &Configurator) -> ParseContext {
+        let (frontmatter, markdown) = Frontmatter::load(input);
+        let markdown = strip_comments(markdown, configurator.strip_html_comments);
+        let markdown: Vec<_> = markdown.lines().map(|x| Arc::from(x.trim_end())).collect();
+
+        Markdown2Html::analyze_input(markdown, frontmatter)
     }
 
     pub fn generate_html(&self) -> String {
-        if self.number_of_threads == 0 {
+        let html = if self.number_of_threads == 0 {
             // default behaviour
             self.generate_html_multi_threaded(self.number_of_threads)
         } else if self.number_of_threads == 1 {
             self.generate_html_single_threaded()
         } else {
             self.generate_html_multi_threaded(self.number_of_threads)
+        };
+
+        // the first occurrence of a glossary term is known only when the whole document is ready
+        self.configurator.link_glossary_terms(&html)
+    }
+
+    // Configurator with document-level settings from frontmatter applied
+    fn document_configurator(&self) -> Configurator {
+        let mut configurator = self.configurator.clone();
+        if !self.parse_context.lang.is_empty() {
+            configurator.lang = self.parse_context.lang.clone();
+        }
+        configurator
+            .abbreviations
+            .extend(self.parse_context.abbreviations.iter().cloned());
+        if let Some(dir) = &self.source_dir {
+            configurator.source_dir = dir.clone();
         }
+        configurator
     }
 
     fn generate_html_single_threaded(&self) -> String {
         let parse_units = &self.parse_context.parse_units;
         let unit_types = &self.parse_context.unit_types;
         let units_size = parse_units.len();
+        let configurator = self.document_configurator();
 
         let mut output_vec = vec!["".to_owned(); units_size];
 
@@ -112,7 +368,7 @@ impl Markdown2Html {
             let parse_unit = parse_units[i].clone();
             let unit_type = unit_types[i];
 
-            output_vec[i] = process_unit(parse_unit, unit_type, &self.configurator);
+            output_vec[i] = process_unit(parse_unit, unit_type, &configurator, self.tight);
         }
 
         let html_body = output_vec.join("\n");
@@ -133,7 +389,8 @@ impl Markdown2Html {
         let unit_types = &self.parse_context.unit_types;
         let units_size = parse_units.len();
 
-        let chunk_size = (units_size + number_of_threads - 1) / number_of_threads; // Calculate chunk size
+        let chunk_size = units_size.div_ceil(number_of_threads); // Calculate chunk size
+        let document_configurator = self.document_configurator();
 
         // Wrap each output element in Arc<Mutex<String>> for thread-safe mutability
         let output_vec: Arc<Vec<Arc<Mutex<String>>>> = Arc::new(
@@ -159,7 +416,8 @@ impl Markdown2Html {
             let unit_types_chunk = get_chunk(unit_types, chunk_start, chunk_size);
             let output_chunk = get_chunk(&output_vec, chunk_start, chunk_size);
 
-            let configurator = self.configurator.clone();
+            let configurator = document_configurator.clone();
+            let tight = self.tight;
 
             let handle = thread::spawn(move || {
                 for (item, (unit_type, output)) in input_chunk
@@ -167,7 +425,7 @@ impl Markdown2Html {
                     .zip(unit_types_chunk.into_iter().zip(output_chunk))
                 {
                     let mut output = output.lock().unwrap();
-                    *output = process_unit(item.clone(), unit_type, &configurator);
+                    *output = process_unit(item.clone(), unit_type, &configurator, tight);
                 }
             });
 
@@ -204,6 +462,12 @@ impl Markdown2Html {
             } else {
                 vec![]
             },
+            lang: if let Some(frontmatter) = &frontmatter {
+                frontmatter.get_string("lang")
+            } else {
+                String::new()
+            },
+            abbreviations: vec![],
         };
 
         let mut h1_counter: usize = 0;
@@ -212,15 +476,45 @@ impl Markdown2Html {
         let mut multiline_counter: usize = 0;
 
         let mut block_start: usize = 0;
+        let mut html_block_end = HtmlBlockEnd::BlankLine;
+        let mut current_list_marker = '-';
+        // containers may be nested, `:::` inside code blocks doesn't count
+        let mut container_depth = 0usize;
+        let mut inside_container_code = false;
+
+        // setext underline already consumed by its header
+        let mut skip_line = false;
 
         'outer: for (i, block) in input.iter().enumerate() {
+            if skip_line {
+                skip_line = false;
+                continue;
+            }
+
             if multiline_state {
                 let state_type = context.unit_types.last().unwrap();
                 match state_type {
                     UnitType::List => {
-                        if block.starts_with("- ")
+                        // another marker starts a new list
+                        if (list_marker(block) == Some(current_list_marker)
+                            || block.starts_with(' ')
+                            || block.trim().is_empty())
+                            && !is_thematic_break(block)
+                        {
+                            multiline_counter += 1;
+                            continue;
+                        } else {
+                            context.parse_units.push(Arc::from(
+                                &input[block_start..block_start + multiline_counter],
+                            ));
+                            multiline_state = false;
+                        }
+                    }
+                    UnitType::DefinitionList => {
+                        if definition_text(block).is_some()
                             || block.starts_with(' ')
                             || block.trim().is_empty()
+                            || is_definition_list_start(&input[i..])
                         {
                             multiline_counter += 1;
                             continue;
@@ -264,14 +558,120 @@ impl Markdown2Html {
                         }
                         continue;
                     }
+                    UnitType::Container | UnitType::Details => {
+                        multiline_counter += 1;
+
+                        let (is_start, is_end) = if *state_type == UnitType::Container {
+                            (container_start(block).is_some(), is_container_end(block))
+                        } else {
+                            (details_start(block).is_some(), is_details_end(block))
+                        };
+
+                        if block.trim_start().starts_with("```") {
+                            inside_container_code = !inside_container_code;
+                        } else if !inside_container_code {
+                            if is_start {
+                                container_depth += 1;
+                            } else if is_end {
+                                container_depth -= 1;
+                            }
+                        }
+
+                        if container_depth == 0 {
+                            context.parse_units.push(Arc::from(
+                                &input[block_start..block_start + multiline_counter],
+                            ));
+                            multiline_state = false;
+                        }
+                        continue;
+                    }
+                    UnitType::RawText => {
+                        if html_block_end == HtmlBlockEnd::BlankLine {
+                            if html_block_ends(block, html_block_end) {
+                                context.parse_units.push(Arc::from(
+                                    &input[block_start..block_start + multiline_counter],
+                                ));
+                                multiline_state = false;
+                            } else {
+                                multiline_counter += 1;
+                                continue;
+                            }
+                        } else {
+                            multiline_counter += 1;
+
+                            if html_block_ends(block, html_block_end) {
+                                context.parse_units.push(Arc::from(
+                                    &input[block_start..block_start + multiline_counter],
+                                ));
+                                multiline_state = false;
+                            }
+                            continue;
+                        }
+                    }
 
                     _ => {}
                 }
             }
 
+            if let Some((term, title)) = abbreviation_definition(block) {
+                context
+                    .abbreviations
+                    .push((term.to_string(), title.to_string()));
+                continue;
+            }
+
+            if let Some(end) = html_block_start(block) {
+                context.unit_types.push(UnitType::RawText);
+                html_block_end = end;
+                block_start = i;
+                multiline_counter = 1;
+
+                // the block may end on its first line, e.g. `<!-- comment -->`. Searching past
+                // `<!` or `<?` so the opening sequence is not taken for the end marker
+                if matches!(end, HtmlBlockEnd::Marker(_)) && html_block_ends(&block[2..], end)
+                    || end == HtmlBlockEnd::BlankLine && is_one_line_element(block)
+                {
+                    context.parse_units.push(Arc::from(&input[i..i + 1]));
+                } else {
+                    multiline_state = true;
+                }
+                continue;
+            }
+
+            // takes precedence over `- ` and `* ` list items
+            if is_thematic_break(block) {
+                context.unit_types.push(UnitType::HorizontalLine);
+                context.parse_units.push(Arc::from(&input[i..i + 1]));
+                continue;
+            }
+
+            if let Some(marker) = list_marker(block) {
+                context.unit_types.push(UnitType::List);
+                current_list_marker = marker;
+                multiline_state = true;
+                multiline_counter = 1;
+                block_start = i;
+                continue;
+            }
+
+            if container_start(block).is_some() || details_start(block).is_some() {
+                context
+                    .unit_types
+                    .push(if container_start(block).is_some() {
+                        UnitType::Container
+                    } else {
+                        UnitType::Details
+                    });
+                container_depth = 1;
+                inside_container_code = false;
+                multiline_state = true;
+                multiline_counter = 1;
+                block_start = i;
+                continue;
+            }
+
             // multiline patterns
             for (pattern, unit_type) in [
-                ("- ", UnitType::List),
                 ("$$", UnitType::Latex),
                 ("```", UnitType::Code),
                 (">", UnitType::Blockquote),
@@ -285,31 +685,45 @@ impl Markdown2Html {
                 }
             }
 
+            if let Some((level, _)) = atx_header(block) {
+                context.unit_types.push(UnitType::Header(level));
+                context.parse_units.push(Arc::from(&input[i..i + 1]));
+
+                if level == 1 {
+                    h1_counter += 1;
+                }
+                continue;
+            }
+
             // one-line patterns
-            for (pattern, unit_type) in [
-                ("# ", UnitType::Header(1)),
-                ("## ", UnitType::Header(2)),
-                ("### ", UnitType::Header(3)),
-                ("#### ", UnitType::Header(4)),
-                ("##### ", UnitType::Header(5)),
-                ("###### ", UnitType::Header(6)),
-                ("![[", UnitType::LocalLink),
-                ("![", UnitType::Image),
-                ("---", UnitType::HorizontalLine),
-                ("<", UnitType::RawText),
-            ] {
+            for (pattern, unit_type) in [("![[", UnitType::LocalLink), ("![", UnitType::Image)] {
                 if block.starts_with(pattern) {
                     context.unit_types.push(unit_type);
                     context.parse_units.push(Arc::from(&input[i..i + 1]));
-
-                    if unit_type == UnitType::Header(1) {
-                        h1_counter += 1;
-                    }
                     continue 'outer;
                 }
             }
 
+            if is_definition_list_start(&input[i..]) {
+                context.unit_types.push(UnitType::DefinitionList);
+                multiline_state = true;
+                multiline_counter = 1;
+                block_start = i;
+                continue;
+            }
+
             if !input[i].is_empty() {
+                if let Some(level) = input.get(i + 1).and_then(|x| setext_underline(x)) {
+                    context.unit_types.push(UnitType::Header(level));
+                    context.parse_units.push(Arc::from(&input[i..i + 1]));
+
+                    if level == 1 {
+                        h1_counter += 1;
+                    }
+                    skip_line = true;
+                    continue;
+                }
+
                 context.unit_types.push(UnitType::Text);
                 context.parse_units.push(Arc::from(&input[i..i + 1]));
             }
@@ -317,7 +731,13 @@ impl Markdown2Html {
 
         if multiline_state {
             let state_type = *context.unit_types.last().unwrap();
-            if state_type == UnitType::List || state_type == UnitType::Blockquote {
+            if state_type == UnitType::List
+                || state_type == UnitType::DefinitionList
+                || state_type == UnitType::Blockquote
+                || state_type == UnitType::Container
+                || state_type == UnitType::Details
+                || state_type == UnitType::RawText
+            {
                 context.parse_units.push(Arc::from(
                     &input[block_start..block_start + multiline_counter],
                 ));
@@ -355,55 +775,199 @@ impl Markdown2Html {
     }
 }
 
+// Up to three spaces of indentation are allowed before block markers
+fn strip_block_indent(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() <= 3 {
+        Some(trimmed)
+    } else {
+        None
+    }
+}
+
+// `***`, `- - -`, `___` etc. Three or more of the same character, spaces are allowed in between
+fn is_thematic_break(line: &str) -> bool {
+    let Some(line) = strip_block_indent(line) else {
+        return false;
+    };
+
+    let mut chars = line.chars().filter(|c| !c.is_whitespace());
+    match chars.next() {
+        Some(marker @ ('-' | '*' | '_')) => {
+            let mut count = 1;
+            for c in chars {
+                if c != marker {
+                    return false;
+                }
+                count += 1;
+            }
+            count >= 3
+        }
+        _ => false,
+    }
+}
+
+// Returns header level and text with the optional closing `#` sequence removed: `## Title ##`
+fn atx_header(line: &str) -> Option<(Level, &str)> {
+    let line = strip_block_indent(line)?;
+    let level = line.chars().take_while(|&c| c == '#').count();
+
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None; // `#hashtag`
+    }
+
+    let text = rest.trim();
+    let without_closing = text.trim_end_matches('#');
+    let text = if without_closing.is_empty() {
+        without_closing
+    } else if without_closing.ends_with([' ', '\t']) {
+        without_closing.trim_end()
+    } else {
+        text // `# C#`
+    };
+
+    Some((level as Level, text))
+}
+
+// `===` makes the previous line a first level header, `---` a second level one
+fn setext_underline(line: &str) -> Option<Level> {
+    let line = strip_block_indent(line)?.trim_end();
+
+    if !line.is_empty() && line.chars().all(|c| c == '=') {
+        Some(1)
+    } else if !line.is_empty() && line.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
 fn process_unit(
     markdown_unit: ParseUnit,
     unit_type: UnitType,
     configurator: &Configurator,
+    tight: bool,
 ) -> String {
     if let UnitType::Header(level) = unit_type {
         return process_header(level, markdown_unit, configurator);
     }
+    if unit_type == UnitType::Text {
+        return process_text(markdown_unit, configurator, tight);
+    }
 
     let f = match unit_type {
-        UnitType::Text => process_text,
         UnitType::List => process_list,
+        UnitType::DefinitionList => process_definition_list,
         UnitType::Image => process_image,
         UnitType::LocalLink => process_local_link,
         UnitType::Latex => process_latex,
         UnitType::Code => process_code,
         UnitType::Blockquote => process_blockquote,
+        UnitType::Container => process_container,
+        UnitType::Details => process_details,
         UnitType::HorizontalLine => process_horizontal_line,
         UnitType::RawText => process_raw_text,
-        _ => process_text,
+        _ => unreachable!(),
     };
 
     f(markdown_unit, configurator)
 }
 
-fn process_text(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
+fn process_text(markdown_unit: ParseUnit, configurator: &Configurator, tight: bool) -> String {
     assert_eq!(markdown_unit.len(), 1);
 
     let text = markdown_unit.first().unwrap().trim();
+    let (text, attributes) = split_block_attributes(text);
     let text = process_inline_formatting(text, configurator);
-    configurator.process_paragraph(&text)
+    if tight {
+        configurator.process_tight_paragraph(&text, &attributes)
+    } else {
+        configurator.process_paragraph(&text, &attributes)
+    }
 }
 
 fn process_header(level: Level, markdown_unit: ParseUnit, configurator: &Configurator) -> String {
     assert_eq!(markdown_unit.len(), 1);
 
-    let text = markdown_unit
-        .first()
-        .unwrap()
-        .trim_start_matches('#')
-        .trim();
-    let text = process_inline_formatting(text, configurator);
-    configurator.process_header(level, &text)
+    let line = markdown_unit.first().unwrap();
+    // setext headers come without `#`
+    let text = atx_header(line).map_or(line.trim(), |(_, text)| text);
+    let (text, attributes) = split_block_attributes(text);
+    // abbreviations and glossary terms are not marked up in headers
+    let mut stash = InlineStash::default();
+    let text = process_inline_stashed(text.to_string(), &mut stash, configurator, false);
+    let text = stash.restore(text);
+    configurator.process_header(level, &text, &attributes)
+}
+
+// `*[HTML]: HyperText Markup Language`
+fn abbreviation_definition(line: &str) -> Option<(&str, &str)> {
+    let (term, title) = line.strip_prefix("*[")?.split_once("]:")?;
+    (!term.trim().is_empty()).then(|| (term.trim(), title.trim()))
+}
+
+// Attribute list separated by a space from a paragraph, a header or a code info string:
+// `## Header {#id .class}`
+fn split_block_attributes(text: &str) -> (&str, Attributes) {
+    match Attributes::split_trailing(text) {
+        Some((rest, attributes))
+            if rest.ends_with(char::is_whitespace) && !rest.trim().is_empty() =>
+        {
+            (rest.trim_end(), attributes)
+        }
+        _ => (text, Attributes::default()),
+    }
 }
 
 fn count_leading_spaces(s: &str) -> usize {
     s.chars().take_while(|&c| c == ' ').count()
 }
 
+// `- `, `* ` or `+ `
+fn list_marker(line: &str) -> Option<char> {
+    let marker = line.chars().next()?;
+    ("-*+".contains(marker) && line[1..].starts_with(' ')).then_some(marker)
+}
+
+// List is loose if its items, or blocks inside an item, are separated by blank lines. Blank lines
+// inside a nested list make only that list loose
+fn is_loose_list(lines: &[Block]) -> bool {
+    let mut inside_code = false;
+    let mut blank_line_met = false;
+    // indentation of a list nested into the current item
+    let mut nested_indent: Option<usize> = None;
+
+    for line in lines {
+        if line.trim().is_empty() {
+            blank_line_met |= !inside_code;
+            continue;
+        }
+
+        let indent = count_leading_spaces(line);
+        let inside_nested = nested_indent.is_some_and(|x| indent >= x);
+        if blank_line_met && !inside_nested {
+            return true;
+        }
+        blank_line_met = false;
+
+        if !inside_nested {
+            nested_indent =
+                (indent > 0 && list_marker(line.trim_start()).is_some()).then_some(indent);
+        }
+
+        if line.trim_start().starts_with("```") {
+            inside_code = !inside_code;
+        }
+    }
+
+    false
+}
+
 fn process_list(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
     #[derive(PartialEq)]
     enum State {
@@ -417,32 +981,22 @@ fn process_list(markdown_unit: ParseUnit, configurator: &Configurator) -> String
     let mut multiline_range = 0usize..0usize;
     let mut i = 0usize;
 
+    // items of a tight list are not wrapped into paragraphs
+    let tight = !is_loose_list(&markdown_unit);
+
     let gen_one_line_html = |multiline_range: &Range<usize>| -> String {
-        let text = markdown_unit[multiline_range.start]
-            .trim()
-            .trim_start_matches('-')
-            .trim();
+        let text = markdown_unit[multiline_range.start].trim()[1..].trim();
         let text = process_inline_formatting(text, configurator);
-        format!("\t<li>{}</li>\n", text)
+        let html = if tight {
+            text
+        } else {
+            configurator.process_paragraph(&text, &Attributes::default())
+        };
+        format!("\t<li>{}</li>\n", html)
     };
 
     let gen_multi_line_html = |blocks: &[Block], ident: usize| -> String {
-        let sub_doc = blocks
-            .iter()
-            .enumerate()
-            .map(|(i, x)| {
-                let to_skip = if i == 0 { "- ".len() } else { ident };
-                x.chars().skip(to_skip).collect::<String>()
-            })
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        let mut parser = Markdown2Html::new(sub_doc);
-        parser.configurator = configurator.clone();
-        parser.configurator.prologue = String::new();
-        parser.configurator.epilogue = String::new();
-
-        let html = parser.generate_html_single_threaded();
+        let html = process_nested_blocks(blocks, "- ".len(), ident, configurator, tight);
         format!("\t<li>{}</li>\n", html)
     };
 
@@ -467,7 +1021,7 @@ fn process_list(markdown_unit: ParseUnit, configurator: &Configurator) -> String
                 } else {
                     res += &gen_one_line_html(&multiline_range);
 
-                    if line.starts_with("- ") {
+                    if list_marker(line).is_some() {
                         state = State::NewElementStart;
                         continue; // no `i` advance
                     } else {
@@ -482,7 +1036,7 @@ fn process_list(markdown_unit: ParseUnit, configurator: &Configurator) -> String
                     // end of multiline
                     res += &gen_multi_line_html(&markdown_unit[multiline_range.clone()], ident);
 
-                    if line.starts_with("- ") {
+                    if list_marker(line).is_some() {
                         state = State::NewElementStart;
                         continue; // no `i` advance
                     } else {
@@ -509,15 +1063,134 @@ fn process_list(markdown_unit: ParseUnit, configurator: &Configurator) -> String
     res
 }
 
+// Renders blocks nested into a list item or a definition as a separate document. The first line
+// is stripped of its marker, the rest of their indentation
+fn process_nested_blocks(
+    blocks: &[Block],
+    marker_len: usize,
+    ident: usize,
+    configurator: &Configurator,
+    tight: bool,
+) -> String {
+    let sub_doc = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let to_skip = if i == 0 { marker_len } else { ident };
+            x.chars().skip(to_skip).collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Markdown2Html::new_nested(&sub_doc, configurator, tight).generate_html_single_threaded()
+}
+
+// Text of a definition line: `: definition`
+fn definition_text(line: &str) -> Option<&str> {
+    line.strip_prefix(": ")
+}
+
+// One or more term lines followed by a definition
+fn is_definition_list_start(lines: &[Block]) -> bool {
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() || line.starts_with(' ') {
+            return false;
+        }
+        if definition_text(line).is_some() {
+            return i > 0;
+        }
+    }
+
+    false
+}
+
+fn process_definition_list(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
+    // definitions of a tight list are not wrapped into paragraphs
+    let tight = !is_loose_list(&markdown_unit);
+
+    let gen_definition_html = |blocks: &[Block]| -> String {
+        // trailing blank lines belong to no definition
+        let len = blocks.len()
+            - blocks
+                .iter()
+                .rev()
+                .take_while(|x| x.trim().is_empty())
+                .count();
+        let blocks = &blocks[..len];
+
+        let html = if blocks.len() == 1 {
+            let text = process_inline_formatting(
+                definition_text(&blocks[0]).unwrap().trim(),
+                configurator,
+            );
+            if tight {
+                text
+            } else {
+                configurator.process_paragraph(&text, &Attributes::default())
+            }
+        } else {
+            let ident = blocks[1..]
+                .iter()
+                .find(|x| !x.trim().is_empty())
+                .map_or(0, |x| count_leading_spaces(x));
+            process_nested_blocks(blocks, ": ".len(), ident, configurator, tight)
+        };
+        configurator.process_definition(&html)
+    };
+
+    let mut items = vec![];
+    let mut definition_start: Option<usize> = None;
+
+    for (i, line) in markdown_unit.iter().enumerate() {
+        let continuation = line.starts_with(' ') || line.trim().is_empty();
+        if continuation && definition_start.is_some() {
+            continue;
+        }
+
+        if let Some(start) = definition_start.take() {
+            items.push(gen_definition_html(&markdown_unit[start..i]));
+        }
+
+        if definition_text(line).is_some() {
+            definition_start = Some(i);
+        } else if !continuation {
+            let text = process_inline_formatting(line.trim(), configurator);
+            items.push(configurator.process_definition_term(&text));
+        }
+    }
+
+    if let Some(start) = definition_start {
+        items.push(gen_definition_html(&markdown_unit[start..]));
+    }
+
+    configurator.process_definition_list(&items.join("\n"))
+}
+
 fn process_image(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
     assert_eq!(markdown_unit.len(), 1);
 
-    let text = markdown_unit.first().unwrap().trim();
-    let caption = &text[2..text.find("](").unwrap()];
+    let mut text = markdown_unit.first().unwrap().trim();
+    let mut attributes = Attributes::default();
+
+    // `![caption](src){.class}`
+    if let Some((rest, attrs)) = Attributes::split_trailing(text) {
+        if rest.ends_with(')') {
+            text = rest;
+            attributes = attrs;
+        }
+    }
+
+    // `![no close` is not an image, it's shown as text
+    let Some((caption, destination)) = text[2..]
+        .split_once("](")
+        .filter(|(_, destination)| destination.ends_with(')'))
+    else {
+        return process_text(markdown_unit, configurator, false);
+    };
     let caption = process_inline_formatting(caption, configurator);
-    let src = &text[text.find("](").unwrap() + 2..text.len() - 1];
+    let (src, title) = split_link_destination(&destination[..destination.len() - 1]);
 
-    configurator.process_image(src, &caption)
+    configurator.process_image(src, &caption, title, &attributes)
 }
 
 fn process_local_link(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
@@ -532,23 +1205,47 @@ fn insert_error_element(error_text: &str, configurator: &Configurator) -> String
 
 fn process_latex(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
     let text = markdown_unit.join("\n");
-    configurator.process_latex(&text)
+    configurator.process_latex(&escape_text(&text))
 }
 
 fn process_code(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
     assert!(markdown_unit.len() >= 2);
 
-    let lang = markdown_unit
+    let info = markdown_unit
         .first()
         .unwrap()
         .trim_start_matches('`')
         .trim();
+    let info = CodeInfo::parse(info);
 
-    let code = markdown_unit[1..markdown_unit.len() - 1].join("\n");
-    let code = escape_characters(code);
-    let (lang, code) = highlight_code(lang, code.as_str());
-
-    configurator.process_code(&lang, &code)
+    let code = if info.file.is_empty() {
+        markdown_unit[1..markdown_unit.len() - 1].join("\n")
+    } else {
+        match configurator.include_file(&info) {
+            Ok(code) => code,
+            Err(err) => return configurator.process_error(&escape_text(&err)),
+        }
+    };
+    let lang = if info.lang.is_empty() && configurator.detect_languages {
+        detect_language(&code, &configurator.languages).map_or(String::new(), |x| x.name.clone())
+    } else {
+        info.lang.clone()
+    };
+    let (lang, code) = highlight_code(
+        &lang,
+        &code,
+        &configurator.languages,
+        &configurator.highlight,
+    );
+    let code = info.render_lines(&code);
+
+    // the block is still shown, after the problems of its info string
+    let errors = info
+        .errors
+        .iter()
+        .map(|err| configurator.process_error(&escape_text(err)) + "\n")
+        .collect::<String>();
+    errors + &configurator.process_code(&lang, &code, &info.title, &info.attributes)
 }
 
 fn process_blockquote(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
@@ -562,20 +1259,129 @@ fn process_blockquote(markdown_unit: ParseUnit, configurator: &Configurator) ->
     configurator.process_blockquote(&text)
 }
 
+// `::: name title` opening a container. Returns its name and title
+fn container_start(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix(":::")?.trim_start_matches(':').trim();
+    let (name, title) = rest.split_once(' ').unwrap_or((rest, ""));
+
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    valid_name.then_some((name, title.trim()))
+}
+
+fn is_container_end(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 3 && line.chars().all(|c| c == ':')
+}
+
+fn process_container(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
+    let (name, title) = container_start(&markdown_unit[0]).unwrap();
+    let title = process_inline_formatting(title, configurator);
+
+    // an unterminated container lasts until the end of the document
+    let mut body = &markdown_unit[1..];
+    if body.last().is_some_and(|x| is_container_end(x)) {
+        body = &body[..body.len() - 1];
+    }
+
+    // a container inside a tight list item has its own paragraphs
+    let text = process_nested_blocks(body, 0, 0, configurator, false);
+
+    configurator.process_container(name, &title, &text)
+}
+
+// `+++ summary` opening a collapsible section
+fn details_start(line: &str) -> Option<&str> {
+    let summary = line.strip_prefix("+++")?.trim_start_matches('+');
+    (summary.starts_with(' ') && !summary.trim().is_empty()).then(|| summary.trim())
+}
+
+fn is_details_end(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 3 && line.chars().all(|c| c == '+')
+}
+
+fn process_details(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
+    let summary = details_start(&markdown_unit[0]).unwrap();
+    let (summary, attributes) = split_block_attributes(summary);
+    let summary = process_inline_formatting(summary, configurator);
+
+    // an unterminated section lasts until the end of the document
+    let mut body = &markdown_unit[1..];
+    if body.last().is_some_and(|x| is_details_end(x)) {
+        body = &body[..body.len() - 1];
+    }
+
+    let text = process_nested_blocks(body, 0, 0, configurator, false);
+
+    configurator.process_details(&summary, &text, &attributes)
+}
+
 fn process_horizontal_line(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
     assert_eq!(markdown_unit.len(), 1);
     configurator.process_horizontal_line()
 }
 
-fn process_raw_text(markdown_unit: ParseUnit, _configurator: &Configurator) -> String {
-    assert_eq!(markdown_unit.len(), 1);
-    markdown_unit.first().unwrap().trim().to_string()
+fn process_raw_text(markdown_unit: ParseUnit, configurator: &Configurator) -> String {
+    let html = markdown_unit.join("\n");
+    configurator.process_raw_html(html.trim())
+}
+
+// Fragments which must not be touched by text-level passes (code spans, math, links, URLs) are
+// replaced with placeholders and put back when the whole inline processing is done
+#[derive(Default)]
+struct InlineStash {
+    items: Vec<String>,
+}
+
+pub(crate) const STASH_START: char = '\u{E000}';
+pub(crate) const STASH_END: char = '\u{E001}';
+
+impl InlineStash {
+    fn push(&mut self, html: String) -> String {
+        self.items.push(html);
+        format!("{STASH_START}{}{STASH_END}", self.items.len() - 1)
+    }
+
+    fn restore(self, text: String) -> String {
+        // items may contain placeholders of earlier items (e.g. code in a link caption)
+        self.items
+            .into_iter()
+            .enumerate()
+            .rev()
+            .fold(text, |acc, (i, html)| {
+                acc.better_replace(&format!("{STASH_START}{i}{STASH_END}"), &html)
+            })
+    }
 }
 
 fn process_inline_formatting(s: impl Into<String>, configurator: &Configurator) -> String {
-    let mut res = s.into();
+    let mut stash = InlineStash::default();
+    let res = process_inline_stashed(s.into(), &mut stash, configurator, true);
+    stash.restore(res)
+}
 
-    res = escape_characters(res);
+fn process_inline_stashed(
+    s: String,
+    stash: &mut InlineStash,
+    configurator: &Configurator,
+    with_terms: bool,
+) -> String {
+    let mut res = stash_code_spans(&s, stash, configurator);
+    res = stash_inline_math(&res, stash);
+    res = stash_inline_html(&res, stash, configurator);
+    res = stash_links(&res, stash, configurator);
+    res = stash_urls(&res, stash);
+
+    res = escape_text_keep_entities(&res);
+    if with_terms {
+        res = configurator.mark_glossary_terms(&res);
+        res = configurator.process_abbreviations(&res, |html| stash.push(html));
+    }
+    res = configurator.process_emoji(&res);
+    res = configurator.process_typography(&res);
     res = process_symmetric_inline_pattern(&res, "***", false, |text| {
         configurator.process_italic_bold(text)
     });
@@ -591,19 +1397,144 @@ fn process_inline_formatting(s: impl Into<String>, configurator: &Configurator)
     });
     res =
         process_symmetric_inline_pattern(&res, "_", true, |text| configurator.process_italic(text));
-    res = process_symmetric_inline_pattern(&res, "`", false, |text| {
-        configurator.process_code_inline(text)
-    });
     res = process_symmetric_inline_pattern(&res, "~~", false, |text| {
         configurator.process_strikethrough(text)
     });
-    res = process_links(&res, configurator);
 
     res
 }
 
-fn escape_characters(text: String) -> String {
-    text.better_replace("<", "&lt;").better_replace(">", "&gt;")
+fn stash_code_spans(text: &str, stash: &mut InlineStash, configurator: &Configurator) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('`') {
+        let ticks = count_backticks(&rest[start..]);
+        let after = &rest[start + ticks..];
+
+        if let Some(end) = find_closing_backticks(after, ticks) {
+            let code = &after[..end];
+            let code = if code.len() > 2 && code.starts_with(' ') && code.ends_with(' ') {
+                &code[1..code.len() - 1]
+            } else {
+                code
+            };
+
+            res.push_str(&rest[..start]);
+            res += &stash.push(configurator.process_code_inline(&escape_text(code)));
+            rest = &after[end + ticks..];
+        } else {
+            res.push_str(&rest[..start + ticks]);
+            rest = after;
+        }
+    }
+
+    res.push_str(rest);
+    res
+}
+
+// `$x$` is kept as is for external LaTeX libraries. Opening `$` must be followed and closing `$`
+// preceded by a non-space character, and closing `$` must not be followed by a digit: `$5 or $10`
+fn stash_inline_math(text: &str, stash: &mut InlineStash) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        let after = &rest[start + 1..];
+
+        let end = if after.starts_with(|c: char| !c.is_whitespace() && c != '$') {
+            after.find('$').filter(|&end| {
+                !after[..end].ends_with(char::is_whitespace)
+                    && !after[end + 1..].starts_with(|c: char| c.is_ascii_digit())
+            })
+        } else {
+            None
+        };
+
+        if let Some(end) = end {
+            res.push_str(&rest[..start]);
+            res += &stash.push(escape_text(&rest[start..start + end + 2]));
+            rest = &after[end + 1..];
+        } else {
+            res.push_str(&rest[..start + 1]);
+            rest = after;
+        }
+    }
+
+    res.push_str(rest);
+    res
+}
+
+// Tags inside a paragraph, like `<kbd>Ctrl</kbd>`, are passed as is or sanitized in safe mode.
+// Content of `<script>` and similar elements goes together with the tags
+fn stash_inline_html(text: &str, stash: &mut InlineStash, configurator: &Configurator) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        res.push_str(&rest[..start]);
+        let after = &rest[start..];
+
+        let Some((tag, mut len)) = parse_tag(after) else {
+            res.push('<');
+            rest = &after[1..];
+            continue;
+        };
+
+        if !tag.closing && !tag.self_closing && DROP_WITH_CONTENT.contains(&tag.name.as_str()) {
+            let closing = format!("</{}>", tag.name);
+            if let Some(end) = after.to_lowercase().find(&closing) {
+                len = end + closing.len();
+            }
+        }
+
+        res += &stash.push(configurator.process_inline_html(&after[..len]));
+        rest = &after[len..];
+    }
+
+    res.push_str(rest);
+    res
+}
+
+// Bare URLs are not turned into links, they are just protected from text-level passes
+fn stash_urls(text: &str, stash: &mut InlineStash) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| rest.find(scheme))
+        .min()
+    {
+        let len = rest[start..]
+            .find(|c: char| c.is_whitespace() || c == STASH_START || c == '<' || c == '>')
+            .unwrap_or(rest.len() - start);
+
+        let mut url = &rest[start..start + len];
+        loop {
+            let trimmed = url.trim_end_matches(|c: char| ".,;:!?*_~\"'".contains(c));
+            // closing parenthesis belongs to the url only if it is balanced: `wiki/Foo_(bar)`
+            let trimmed = if trimmed.ends_with(')')
+                && trimmed.matches(')').count() > trimmed.matches('(').count()
+            {
+                &trimmed[..trimmed.len() - 1]
+            } else {
+                trimmed
+            };
+
+            if trimmed.len() == url.len() {
+                break;
+            }
+            url = trimmed;
+        }
+
+        res.push_str(&rest[..start]);
+        res += &stash.push(escape_text(url));
+        rest = &rest[start + url.len()..];
+    }
+
+    res.push_str(rest);
+    res
 }
 
 fn byte_index_to_char_index(text: &str, byte_index: usize) -> usize {
@@ -668,9 +1599,32 @@ fn process_symmetric_inline_pattern(
     res
 }
 
-fn process_links(text: &str, configurator: &Configurator) -> String {
-    let mut res = text.to_string();
+// Splits `url "title"`, `url 'title'` or `<url with spaces> "title"` into url and title
+fn split_link_destination(destination: &str) -> (&str, &str) {
+    fn unquote(s: &str) -> &str {
+        for quote in ['"', '\''] {
+            if s.len() >= 2 && s.starts_with(quote) && s.ends_with(quote) {
+                return &s[1..s.len() - 1];
+            }
+        }
+        s
+    }
 
+    let destination = destination.trim();
+
+    if let Some(rest) = destination.strip_prefix('<') {
+        if let Some(end) = rest.find('>') {
+            return (&rest[..end], unquote(rest[end + 1..].trim()));
+        }
+    }
+
+    match destination.split_once(char::is_whitespace) {
+        Some((url, title)) => (url, unquote(title.trim())),
+        None => (destination, ""),
+    }
+}
+
+fn stash_links(text: &str, stash: &mut InlineStash, configurator: &Configurator) -> String {
     #[derive(PartialEq)]
     enum State {
         None,
@@ -680,10 +1634,16 @@ fn process_links(text: &str, configurator: &Configurator) -> String {
     }
 
     let mut state = State::None;
+    // end of an attribute list which follows a link
+    let mut skip_until = 0usize;
 
-    let mut to_replace: Vec<(Range<usize>, &str, &str)> = vec![];
+    let mut to_replace: Vec<(Range<usize>, &str, &str, Attributes)> = vec![];
 
     for (i, ch) in text.char_indices() {
+        if i < skip_until {
+            continue;
+        }
+
         match state {
             State::None => {
                 if ch == '[' {
@@ -712,23 +1672,38 @@ fn process_links(text: &str, configurator: &Configurator) -> String {
                     let caption = &text[caption_start + 1..link_start - 1];
                     let link = &text[link_start + 1..link_end];
 
-                    to_replace.push((caption_start..link_end + 1, link, caption));
+                    // `[caption](src){.class}`
+                    let mut end = link_end + 1;
+                    let mut attributes = Attributes::default();
+                    if text[end..].starts_with('{') {
+                        if let Some(len) = text[end..].find('}') {
+                            if let Some(attrs) = Attributes::parse(&text[end..end + len + 1]) {
+                                attributes = attrs;
+                                end += len + 1;
+                                skip_until = end;
+                            }
+                        }
+                    }
+
+                    to_replace.push((caption_start..end, link, caption, attributes));
                     state = State::None;
                 }
             }
         }
     }
 
-    let mut offset_accum: usize = 0;
-    for (r, link, caption) in to_replace {
-        let left_index = r.start + offset_accum;
-        let right_index = r.end + offset_accum;
+    let mut res = String::with_capacity(text.len());
+    let mut last_end: usize = 0;
+    for (r, link, caption, attributes) in to_replace {
+        res.push_str(&text[last_end..r.start]);
 
-        let processed = configurator.process_link(link, caption);
+        let caption = process_inline_stashed(caption.to_string(), stash, configurator, false);
+        let (link, title) = split_link_destination(link);
+        res += &stash.push(configurator.process_link(link, &caption, title, &attributes));
 
-        offset_accum += processed.len() - r.len();
-        res.replace_range(left_index..right_index, &processed);
+        last_end = r.end;
     }
+    res.push_str(&text[last_end..]);
 
     res
 }
@@ -736,6 +1711,7 @@ fn process_links(text: &str, configurator: &Configurator) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::TestDir;
     use std::time::SystemTime;
 
     #[test]
@@ -766,4 +1742,507 @@ mod tests {
         let _res = generator.generate_html();
         println!("{}", _res);
     }
+
+    fn render(input: &str, config: &str) -> String {
+        let config = format!("[page]\nprologue = ''\nepilogue = ''\n{config}");
+        Markdown2Html::new_with_config(input.to_string(), 1, Some(config)).generate_html()
+    }
+
+    #[test]
+    fn inline_code_and_math_are_protected() {
+        assert_eq!(
+            render("`snake_case_name` and ``a ` b`` in $x_1 * y_2$", ""),
+            "<p><code>snake_case_name</code> and <code>a ` b</code> in $x_1 * y_2$</p>"
+        );
+        assert_eq!(render("costs $5 or $10", ""), "<p>costs $5 or $10</p>");
+        assert_eq!(
+            render("[`Option<T>`](https://doc.rust-lang.org/std/option/)", ""),
+            "<p><a href=\"https://doc.rust-lang.org/std/option/\"><code>Option&lt;T&gt;</code></a></p>"
+        );
+        assert_eq!(
+            render("see https://x.com/a_b_c_d, *done*", ""),
+            "<p>see https://x.com/a_b_c_d, <i>done</i></p>"
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            render("Fish &amp; chips & `&lt;` &copy;", ""),
+            "<p>Fish &amp; chips &amp; <code>&amp;lt;</code> &copy;</p>"
+        );
+        assert_eq!(
+            render("```\na && b < c\n```", ""),
+            "<pre><code>a &amp;&amp; b &lt; c</code></pre>"
+        );
+        assert_eq!(
+            render(r#"[*x*](/a"onclick="b c "T&'s")"#, ""),
+            r#"<p><a href="/a%22onclick=%22b"><i>x</i></a></p>"#
+        );
+        assert_eq!(
+            render(
+                r#"![Say "hi" & < b>](/img.png "A & B")"#,
+                "[tags]\nimage = '<img src=\"{src}\" alt=\"{caption}\" title=\"{title}\"><p>{caption}</p>'"
+            ),
+            "<img src=\"/img.png\" alt=\"Say &quot;hi&quot; &amp; &lt; b&gt;\" title=\"A &amp; B\"><p>Say \"hi\" &amp; &lt; b&gt;</p>"
+        );
+        assert_eq!(
+            render("![a](</my img.png> 'Title')", ""),
+            "<img src=\"/my%20img.png\" alt=\"a\">"
+        );
+    }
+
+    #[test]
+    fn headers_and_breaks() {
+        assert_eq!(
+            render("Title\n=====\nSub *title*\n---\n\nText", ""),
+            "<h1>Title</h1>\n<h2>Sub <i>title</i></h2>\n<p>Text</p>"
+        );
+        assert_eq!(
+            render("## Closed ##\n### C#\n#hashtag\n#\n####### seven", ""),
+            "<h2>Closed</h2>\n<h3>C#</h3>\n<p>#hashtag</p>\n<h1></h1>\n<p>####### seven</p>"
+        );
+        assert_eq!(
+            render("***\n- - -\n __ _\n---trailing text\n\n---", ""),
+            "<hr>\n<hr>\n<hr>\n<p>---trailing text</p>\n<hr>"
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            render("* one\n* two\n+ three\n- four\n  - nested", ""),
+            "<ul>\n\t<li>one</li>\n\t<li>two</li>\n</ul>\n<ul>\n\t<li>three</li>\n</ul>\n<ul>\n\t<li>four\n<ul>\n\t<li>nested</li>\n</ul></li>\n</ul>"
+        );
+        assert_eq!(
+            render("- one\n\n- two\n  ```\n  a\n\n  b\n  ```\n", ""),
+            "<ul>\n\t<li><p>one</p></li>\n\t<li><p>two</p>\n<pre><code>a\n\nb</code></pre></li>\n</ul>"
+        );
+        assert_eq!(
+            render("- one\n- two\n  ```\n  a\n\n  b\n  ```", ""),
+            "<ul>\n\t<li>one</li>\n\t<li>two\n<pre><code>a\n\nb</code></pre></li>\n</ul>"
+        );
+        assert_eq!(
+            render("- one\n  - a\n\n  - b\n- two\n  text {.note}", ""),
+            "<ul>\n\t<li>one\n<ul>\n\t<li><p>a</p></li>\n\t<li><p>b</p></li>\n</ul></li>\n\t<li>two\n<p class=\"note\">text</p></li>\n</ul>"
+        );
+        assert_eq!(render("*not a list*", ""), "<p><i>not a list</i></p>");
+    }
+
+    #[test]
+    fn definition_lists() {
+        assert_eq!(
+            render("Apple\n: Fruit\n: Company\nOrange\nCitrus\n: *Fruit*\nafter", ""),
+            "<dl>\n\t<dt>Apple</dt>\n\t<dd>Fruit</dd>\n\t<dd>Company</dd>\n\t<dt>Orange</dt>\n\t<dt>Citrus</dt>\n\t<dd><i>Fruit</i></dd>\n</dl>\n<p>after</p>"
+        );
+        assert_eq!(
+            render("Term\n: First paragraph\n\n    Second one\n\n: Other\n\nText", ""),
+            "<dl>\n\t<dt>Term</dt>\n\t<dd><p>First paragraph</p>\n<p>Second one</p></dd>\n\t<dd><p>Other</p></dd>\n</dl>\n<p>Text</p>"
+        );
+        assert_eq!(
+            render(
+                "Term\n: def",
+                "[tags]\ndl = '<div>{text}</div>'\ndt = '<b>{text}</b>'\ndd = '<i>{text}</i>'"
+            ),
+            "<div><b>Term</b>\n<i>def</i></div>"
+        );
+        assert_eq!(
+            render(": not a definition", ""),
+            "<p>: not a definition</p>"
+        );
+    }
+
+    #[test]
+    fn containers() {
+        let input = "::: warning Be *careful*\nText\n\n::: note\n```\n:::\n```\n:::\n:::\nafter";
+
+        assert_eq!(
+            render(input, ""),
+            "<div class=\"warning\">\n<p class=\"container-title\">Be <i>careful</i></p>\n<p>Text</p>\n<div class=\"note\">\n<pre><code>:::</code></pre>\n</div>\n</div>\n<p>after</p>"
+        );
+        assert_eq!(
+            render(
+                input,
+                "[tags]\ncontainer = '<section class=\"{name}\">{text}</section>'\n[containers]\nwarning = '<aside title=\"{title}\"><b>{title}</b>{text}</aside>'"
+            ),
+            "<aside title=\"Be careful\"><b>Be <i>careful</i></b><p>Text</p>\n<section class=\"note\"><pre><code>:::</code></pre></section></aside>\n<p>after</p>"
+        );
+        assert_eq!(render("::: a\"b\n:::", ""), "<p>::: a\"b</p>\n<p>:::</p>");
+        assert_eq!(render(":::\n:::*x*", ""), "<p>:::</p>\n<p>:::<i>x</i></p>");
+        assert_eq!(
+            render("::: note\n---\nsecret text\n\n---\n:::", ""),
+            "<div class=\"note\">\n<hr>\n<p>secret text</p>\n<hr>\n</div>"
+        );
+    }
+
+    #[test]
+    fn attribute_lists() {
+        assert_eq!(
+            render("## Header {#top .wide}\nText {.lead data-x=\"1 2\"}\nf(x) {x}", ""),
+            "<h2 id=\"top\" class=\"wide\">Header</h2>\n<p class=\"lead\" data-x=\"1 2\">Text</p>\n<p>f(x) {x}</p>"
+        );
+        assert_eq!(
+            render("![a](/a.png){.right width=50}", ""),
+            "<img src=\"/a.png\" alt=\"a\" class=\"right\" width=\"50\">"
+        );
+        // an image line which is not closed is shown as text
+        assert_eq!(render("![no close", ""), "<p>![no close</p>");
+        assert_eq!(render("![a](", ""), "<p>![a](</p>");
+        assert_eq!(
+            render("[b](/b){#b}text", ""),
+            "<p><a href=\"/b\" id=\"b\">b</a>text</p>"
+        );
+        assert_eq!(
+            render("```{.rust .numbers}\nx\n```\n```js {#code}\ny\n```", ""),
+            "<pre class=\"numbers\"><code class=\"language-rust\">x</code></pre>\n<pre id=\"code\"><code class=\"language-javascript\">y</code></pre>"
+        );
+        assert_eq!(
+            render(
+                "Text {.a #b onclick=x}\n",
+                "[tags]\nparagraph = '<div class=\"p {class}\" data-id=\"{id}\">{text}</div>'\nbold = '<b{attrs}>{text}</b>'\n[options]\nsafe-mode = 'filter'"
+            ),
+            "<div class=\"p a\" data-id=\"b\">Text</div>"
+        );
+    }
+
+    #[test]
+    fn collapsible_sections() {
+        assert_eq!(
+            render("+++ Show *code* {.spoiler}\n```\n+++\n```\n+++ Nested\n\ntext\n+++\n+++\nafter", ""),
+            "<details class=\"spoiler\">\n<summary>Show <i>code</i></summary>\n<pre><code>+++</code></pre>\n<details>\n<summary>Nested</summary>\n<p>text</p>\n</details>\n</details>\n<p>after</p>"
+        );
+        assert_eq!(
+            render(
+                "+++ Spoiler\nHidden",
+                "[tags]\ndetails = '<details open><summary>{summary}</summary>{text}</details>'"
+            ),
+            "<details open><summary>Spoiler</summary><p>Hidden</p></details>"
+        );
+        assert_eq!(render("+++\n+++x", ""), "<p>+++</p>\n<p>+++x</p>");
+        assert_eq!(
+            render("+++ Summary\n---\nbody\n---\nmore\n+++", ""),
+            "<details>\n<summary>Summary</summary>\n<hr>\n<h2>body</h2>\n<p>more</p>\n</details>"
+        );
+    }
+
+    #[test]
+    fn abbreviations_and_glossary() {
+        let input = "# HTML\nHTML and XHTML `HTML` [HTML](/x) **HTML**\n*[HTML]: Hyper \"Text\"";
+        assert_eq!(
+            render(input, ""),
+            "<h1>HTML</h1>\n<p><abbr title=\"Hyper &quot;Text&quot;\">HTML</abbr> and XHTML <code>HTML</code> <a href=\"/x\">HTML</a> <b><abbr title=\"Hyper &quot;Text&quot;\">HTML</abbr></b></p>"
+        );
+
+        let dir = TestDir::new("glossary");
+        let glossary = dir.join("glossary.md");
+        std::fs::write(
+            &glossary,
+            "Voronoi Diagram\n: Partition\n\nCell\n: Part\n\nR&D\n: Research",
+        )
+        .unwrap();
+        let config = format!(
+            "[glossary]\nfile = '{}'\nurl = '/glossary.html#{{anchor}}'",
+            glossary.display()
+        );
+
+        assert_eq!(
+            render(
+                "## Cell\n![cell](/c.png)\nA cell of the voronoi diagram.\n\n- Every cell, cells\n\n[Cell](/x) Cell",
+                &config
+            ),
+            "<h2>Cell</h2>\n<img src=\"/c.png\" alt=\"cell\">\n<p>A <a href=\"/glossary.html#cell\" class=\"glossary\">cell</a> of the <a href=\"/glossary.html#voronoi-diagram\" class=\"glossary\">voronoi diagram</a>.</p>\n<ul>\n\t<li>Every cell, cells</li>\n</ul>\n<p><a href=\"/x\">Cell</a> Cell</p>"
+        );
+        // terms are matched against escaped text, like abbreviations
+        assert_eq!(
+            render("Our R&D team.", &config),
+            "<p>Our <a href=\"/glossary.html#r-d\" class=\"glossary\">R&amp;D</a> team.</p>"
+        );
+        assert_eq!(
+            render("Our R&D team.\n*[R&D]: Research", ""),
+            "<p>Our <abbr title=\"Research\">R&amp;D</abbr> team.</p>"
+        );
+
+        // the glossary file is relative to the configuration file
+        std::fs::write(dir.join("terms.md"), "Cell\n: Part").unwrap();
+        let render_with_config_file = |config: &str| {
+            std::fs::write(dir.join("config.toml"), config).unwrap();
+            Markdown2Html::new_with_config_file("Cell".to_string(), 1, &dir.join("config.toml"))
+                .unwrap()
+                .generate_html()
+        };
+
+        assert_eq!(
+            render_with_config_file(
+                "[page]\nprologue = ''\nepilogue = ''\n[glossary]\nfile = 'terms.md'"
+            ),
+            "<p><a href=\"#cell\" class=\"glossary\">Cell</a></p>"
+        );
+        assert!(render_with_config_file(
+            "[page]\nprologue = ''\nepilogue = ''\n[glossary]\nfile = 'missing.md'"
+        )
+        .starts_with("<div class=\"parse-error\">can't read glossary file 'missing.md': "));
+    }
+
+    #[test]
+    fn code_info_string() {
+        assert_eq!(
+            render("```c linenos=x\na\n```", ""),
+            "<div class=\"parse-error\">invalid first line number 'x'</div>\n<pre><code class=\"language-c\">a</code></pre>"
+        );
+        assert_eq!(
+            render(
+                "```rust title=main.rs {2} linenos\nlet a;\nlet b;\n```",
+                "[tags]\ncode = '<pre title=\"{title}\"{attrs}><code>{text}</code></pre>'"
+            ),
+            "<pre title=\"main.rs\"><code><span class=\"code-line\"><span class=\"code-line-number\">1</span><span class=\"code-keyword\">let</span> a<span class=\"code-punctuation\">;</span></span>\n<span class=\"code-line code-line-highlighted\"><span class=\"code-line-number\">2</span><span class=\"code-keyword\">let</span> b<span class=\"code-punctuation\">;</span></span></code></pre>"
+        );
+    }
+
+    #[test]
+    fn language_files() {
+        // language files are relative to the configuration file
+        let dir = TestDir::new("language_files");
+        std::fs::write(
+            dir.join("gd.toml"),
+            "[language]\naliases = 'gdscript'\nkeywords = 'func'\nline-comments = '#'",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("config.toml"),
+            "[page]\nprologue = ''\nepilogue = ''\n[languages]\ngd = 'gd.toml'\nsh = 'missing.toml'",
+        )
+        .unwrap();
+
+        let html = Markdown2Html::new_with_config_file(
+            "```gdscript\nfunc f() # x\n```".to_string(),
+            1,
+            &dir.join("config.toml"),
+        )
+        .unwrap()
+        .generate_html();
+        assert!(html
+            .starts_with("<div class=\"parse-error\">can't read language file 'missing.toml': "));
+        assert!(html.ends_with(
+            "<pre><code class=\"language-gd\"><span class=\"code-keyword\">func</span> <span class=\"code-call\">f</span>() <span class=\"code-comment\"># x</span></code></pre>"
+        ));
+    }
+
+    #[test]
+    fn highlight_markup() {
+        assert_eq!(
+            render(
+                "```c\nint x; // y\n```",
+                "[highlight]\nkeyword = '<b>{text}</b>'\ntype = '<span class=\"hljs-type\">{text}</span>'\n\
+                 punctuation = ''"
+            ),
+            "<pre><code class=\"language-c\"><span class=\"hljs-type\">int</span> x; <span class=\"code-comment\">// y</span></code></pre>"
+        );
+        assert_eq!(
+            render(
+                "```c\nint x; // y\n```",
+                "[highlight]\ninline-theme = 'light'"
+            ),
+            "<pre><code class=\"language-c\"><span style=\"color: #953800\">int</span> x<span style=\"color: #57606a\">;</span> <span style=\"color: #6e7781; font-style: italic\">// y</span></code></pre>"
+        );
+        assert_eq!(
+            render("```diff\n-a b\n+a c\n```", "[highlight]\nword-diff = true"),
+            "<pre><code class=\"language-diff\"><span class=\"code-diff-remove\">-a <span class=\"code-diff-remove-word\">b</span></span>\n<span class=\"code-diff-add\">+a <span class=\"code-diff-add-word\">c</span></span></code></pre>"
+        );
+        assert_eq!(
+            render(
+                "```c\nint x;\n```",
+                "[highlight]\ntype = '<span class=\"t\">'\ninline-theme = 'unknown'"
+            ),
+            "<div class=\"parse-error\">unknown highlight theme 'unknown'</div>\n\
+             <div class=\"parse-error\">highlight template 'type' has no {text} placeholder</div>\n\
+             <pre><code class=\"language-c\"><span class=\"code-type\">int</span> x\
+             <span class=\"code-punctuation\">;</span></code></pre>"
+        );
+    }
+
+    #[test]
+    fn detect_languages() {
+        let input = "```\ndef f(x):\n    return x\n```\n```\nsome output\n```";
+        let html = render(input, "[options]\ndetect-languages = true");
+        assert!(html.starts_with(
+            "<pre><code class=\"language-python\"><span class=\"code-keyword\">def</span>"
+        ));
+        assert!(html.ends_with("<pre><code>some output</code></pre>"));
+        assert!(render(input, "").starts_with("<pre><code>def "));
+        assert_eq!(
+            render(
+                "```sh\nls\n```\n```\nls\n```",
+                "[tags]\ncode-language = ' data-lang=\"{lang}\"'"
+            ),
+            "<pre><code data-lang=\"bash\">ls</code></pre>\n<pre><code>ls</code></pre>"
+        );
+    }
+
+    #[test]
+    fn code_file_includes() {
+        let dir = TestDir::new("code_file_includes");
+        std::fs::write(dir.join("a.js"), "let a = 1;\nlet b = a < 2;\n").unwrap();
+
+        let input = "```js file=a.js lines=2\n```\n```js file=b.js\n```";
+        let mut generator = Markdown2Html::new_with_config(
+            input.to_string(),
+            1,
+            Some("[page]\nprologue = ''\nepilogue = ''".to_string()),
+        );
+        generator.set_source_dir(dir.path());
+
+        let html = generator.generate_html();
+        let (code, error) = html.split_once('\n').unwrap();
+        assert_eq!(
+            code,
+            "<pre><code class=\"language-javascript\"><span class=\"code-keyword\">let</span> b <span class=\"code-operator\">=</span> a <span class=\"code-operator\">&lt;</span> <span class=\"code-literal\">2</span><span class=\"code-punctuation\">;</span></code></pre>"
+        );
+        assert!(error.starts_with("<div class=\"parse-error\">can't include 'b.js': "));
+
+        assert_eq!(
+            render(input, "[options]\nsafe-mode = 'filter'"),
+            "<div class=\"parse-error\">can't include 'a.js': file includes are disabled in safe mode</div>\n<div class=\"parse-error\">can't include 'b.js': file includes are disabled in safe mode</div>"
+        );
+    }
+
+    #[test]
+    fn html_blocks() {
+        assert_eq!(
+            render(
+                "<details>\n<summary>More</summary>\n\nSome *markdown*\n\n</details>",
+                ""
+            ),
+            "<details>\n<summary>More</summary>\n<p>Some <i>markdown</i></p>\n</details>"
+        );
+        assert_eq!(
+            render("<pre>\nline *one*\n\nline two\n</pre>\nafter", ""),
+            "<pre>\nline *one*\n\nline two\n</pre>\n<p>after</p>"
+        );
+        assert_eq!(
+            render(
+                "<!-- one -->\n<!--\n# not a header\n-->\n<span>x</span> y",
+                ""
+            ),
+            "<!-- one -->\n<!--\n# not a header\n-->\n<p><span>x</span> y</p>"
+        );
+        assert_eq!(
+            render(
+                "<custom-tag attr=\"1\">\n*x*\n\n<https://askepit.github.io>",
+                ""
+            ),
+            "<custom-tag attr=\"1\">\n*x*\n<p>&lt;https://askepit.github.io&gt;</p>"
+        );
+        assert_eq!(
+            render("<b>bold</b> and *text*", ""),
+            "<p><b>bold</b> and <i>text</i></p>"
+        );
+        assert_eq!(
+            render(
+                "<kbd onclick=\"x()\">K</kbd> <script>alert(1)</script>ok",
+                "[options]\nsafe-mode = 'filter'"
+            ),
+            "<p><kbd>K</kbd> ok</p>"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let input = "## In Work <!-- id: 0 -->\n> quote %%private\nnote%%\n> more";
+
+        assert_eq!(
+            render(input, ""),
+            "<h2>In Work &lt;!-- id: 0 --&gt;</h2>\n<blockquote>quote\nmore</blockquote>"
+        );
+        assert_eq!(
+            render(input, "[options]\nstrip-html-comments = true"),
+            "<h2>In Work</h2>\n<blockquote>quote\nmore</blockquote>"
+        );
+        // text after a comment spanning lines stays in the list item
+        for input in [
+            "- item <!-- multi\nline -->end",
+            "- item %%multi\nline%%end",
+        ] {
+            assert_eq!(
+                render(input, "[options]\nstrip-html-comments = true"),
+                "<ul>\n\t<li>item end</li>\n</ul>"
+            );
+        }
+
+        let mut generator = Markdown2Html::new("a <!-- b -->".to_string());
+        generator.set_number_of_threads(1);
+        generator.set_configuration(
+            "[page]\nprologue = ''\nepilogue = ''\n[options]\nstrip-html-comments = true"
+                .to_string(),
+        );
+        assert_eq!(generator.generate_html(), "<p>a</p>");
+    }
+
+    #[test]
+    fn safe_mode() {
+        let input =
+            "<div onclick=\"x()\">hi</div>\n[a](javascript:evil) [b](/ok)\n![i](vbscript:x)";
+
+        assert_eq!(
+            render(input, ""),
+            "<div onclick=\"x()\">hi</div>\n<p><a href=\"javascript:evil\">a</a> <a href=\"/ok\">b</a></p>\n<img src=\"vbscript:x\" alt=\"i\">"
+        );
+        assert_eq!(
+            render(input, "[options]\nsafe-mode = 'filter'"),
+            "<div>hi</div>\n<p>a <a href=\"/ok\">b</a></p>\n<div class=\"parse-error\">i</div>"
+        );
+        assert_eq!(
+            render("<b>x</b>", "[options]\nsafe-mode = 'escape'"),
+            "<p>&lt;b&gt;x&lt;/b&gt;</p>"
+        );
+        assert_eq!(
+            render("<b>x</b>", "[options]\nsafe-mode = 'strip'"),
+            "<p>&lt;b&gt;x&lt;/b&gt;</p>"
+        );
+        assert_eq!(
+            render(
+                "<b>x</b> <u>y</u>",
+                "[options]\nsafe-mode = 'filter'\n[safe-mode]\ntags = 'u'"
+            ),
+            "<p>x <u>y</u></p>"
+        );
+    }
+
+    #[test]
+    fn smart_punctuation() {
+        let config = "[options]\nsmart-punctuation = true";
+
+        assert_eq!(
+            render(r#"Say "hi" -- `"raw" --x` and $a--b$..."#, config),
+            "<p>Say “hi” – <code>\"raw\" --x</code> and $a--b$…</p>"
+        );
+        assert_eq!(
+            render("---\nlang: ru\n---\n\"Привет\"", config),
+            "<p>«Привет»</p>"
+        );
+        assert_eq!(
+            render(
+                "---\nlang: ru\n---\n\"Привет\"",
+                "[options]\nsmart-punctuation = true\n[quotes]\nru = '„“‚‘'"
+            ),
+            "<p>„Привет“</p>"
+        );
+    }
+
+    #[test]
+    fn emoji_shortcodes() {
+        let input = ":rocket: `:rocket:` $:x:$ [:tada:](https://x.com/:tada:) https://y.com/:x:";
+
+        assert!(render(input, "").starts_with("<p>:rocket: "));
+        assert_eq!(
+            render(input, "[options]\nemoji = true"),
+            "<p>🚀 <code>:rocket:</code> $:x:$ <a href=\"https://x.com/:tada:\">🎉</a> https://y.com/:x:</p>"
+        );
+        assert_eq!(
+            render(
+                ":shipit:",
+                "[options]\nemoji = true\n[emoji]\nshipit = ':squirrel:'"
+            ),
+            "<p>🐿️</p>"
+        );
+    }
 }
@@ -1,6 +1,6 @@
 mod cmd;
 
-use downtown::Markdown2Html;
+use downtown::{theme_css, theme_names, Markdown2Html};
 use std::fs::DirEntry;
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -53,10 +53,17 @@ fn process_file(
     config_path: Option<PathBuf>,
     number_of_threads: u8,
 ) -> io::Result<()> {
-    let input = fs::read_to_string(input_path)?;
-    let config = config_path.and_then(|x| fs::read_to_string(x).ok());
+    let input = fs::read_to_string(&input_path)?;
 
-    let parser = Markdown2Html::new_with_config(input, number_of_threads, config);
+    let mut parser = match config_path {
+        Some(config_path) => {
+            Markdown2Html::new_with_config_file(input, number_of_threads, &config_path)?
+        }
+        None => Markdown2Html::new_with_config(input, number_of_threads, None),
+    };
+    if let Some(dir) = input_path.parent() {
+        parser.set_source_dir(dir);
+    }
     let res = parser.generate_html();
 
     let mut f = fs::File::create(output_path)?;
@@ -89,9 +96,15 @@ ALL PARAMETERS
                  created besides each processed *.md file
 
 -c, --config     A path to the configuration *.toml file, where you can fine-tune generator behaviour
+                 Files named in it are relative to its directory
 
 -j, --threads    Number of threads to run. Default is 4
 
+-t, --theme-css  Writes a stylesheet of a built-in code highlighting theme (light, dark or
+                 solarized) to <output> instead of processing Markdown. If <output> is not
+                 specified, <theme>.css is created. Selectors follow the [highlight] section of
+                 the configuration file if it's given
+
 CONFIGURATION FILE SPEC
 =======================
 
@@ -107,6 +120,23 @@ TBD
         return Ok(());
     }
 
+    if let Some(theme) = cmd::get_string_by_tag("-t", "--theme-css") {
+        let config_toml = cmd::get_path_by_tag("-c", "--config")
+            .map(fs::read_to_string)
+            .transpose()?;
+        let Some(css) = theme_css(&theme, config_toml.as_deref()) else {
+            Err(format!(
+                "unknown theme '{theme}', available themes: {}",
+                theme_names().join(", ")
+            ))?
+        };
+
+        let output_path = cmd::get_path_by_tag("-o", "--output")
+            .unwrap_or_else(|| PathBuf::from(theme.to_lowercase()).with_extension("css"));
+        fs::write(output_path, css)?;
+        return Ok(());
+    }
+
     let input_path = cmd::get_path_by_tag("-i", "--input");
 
     if input_path.is_none() {
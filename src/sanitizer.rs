@@ -0,0 +1,313 @@
+use crate::attributes::Attributes;
+use crate::html::{entity_len, escape_text, parse_tag, Tag};
+use std::collections::HashSet;
+
+const DEFAULT_TAGS: &str = "a abbr b blockquote br caption cite code col colgroup dd del details \
+    div dl dt em figcaption figure h1 h2 h3 h4 h5 h6 hr i img ins kbd li mark ol p pre q s samp \
+    small span strong sub summary sup table tbody td tfoot th thead tr u ul var";
+
+const DEFAULT_ATTRIBUTES: &str =
+    "href src alt title class id width height colspan rowspan align open lang dir start";
+
+const DEFAULT_SCHEMES: &str = "http https mailto ftp tel";
+
+// Elements whose content must not leak as text when the element itself is dropped
+pub(crate) const DROP_WITH_CONTENT: [&str; 8] = [
+    "script", "style", "iframe", "object", "embed", "template", "noscript", "textarea",
+];
+
+const URL_ATTRIBUTES: [&str; 6] = ["href", "src", "action", "formaction", "poster", "cite"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SafeMode {
+    // raw HTML is emitted as is
+    Off,
+    // raw HTML is shown as text
+    Escape,
+    // raw HTML is filtered through the allowlist
+    Filter,
+}
+
+#[derive(Clone)]
+pub(crate) struct Sanitizer {
+    pub(crate) mode: SafeMode,
+    tags: HashSet<String>,
+    attributes: HashSet<String>,
+    schemes: HashSet<String>,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Sanitizer::new(SafeMode::Off, None, None, None)
+    }
+}
+
+fn word_set(words: &str) -> HashSet<String> {
+    words.split_whitespace().map(|x| x.to_lowercase()).collect()
+}
+
+impl Sanitizer {
+    pub(crate) fn new(
+        mode: SafeMode,
+        tags: Option<&String>,
+        attributes: Option<&String>,
+        schemes: Option<&String>,
+    ) -> Self {
+        Sanitizer {
+            mode,
+            tags: word_set(tags.map_or(DEFAULT_TAGS, |x| x.as_str())),
+            attributes: word_set(attributes.map_or(DEFAULT_ATTRIBUTES, |x| x.as_str())),
+            schemes: word_set(schemes.map_or(DEFAULT_SCHEMES, |x| x.as_str())),
+        }
+    }
+
+    pub(crate) fn parse_mode(s: &str) -> Option<SafeMode> {
+        match s {
+            "off" => Some(SafeMode::Off),
+            "escape" => Some(SafeMode::Escape),
+            "filter" => Some(SafeMode::Filter),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_url_allowed(&self, url: &str) -> bool {
+        if self.mode == SafeMode::Off {
+            return true;
+        }
+
+        // browsers ignore whitespace and control characters inside a scheme: `java\tscript:`
+        let url = decode_basic_entities(url)
+            .chars()
+            .filter(|c| !c.is_whitespace() && !c.is_control())
+            .collect::<String>()
+            .to_lowercase();
+
+        match url.find([':', '/', '?', '#']) {
+            Some(i) if url[i..].starts_with(':') => self.schemes.contains(&url[..i]),
+            _ => true, // relative url
+        }
+    }
+
+    pub(crate) fn sanitize(&self, html: &str) -> String {
+        match self.mode {
+            SafeMode::Off => html.to_string(),
+            SafeMode::Escape => escape_text(html),
+            SafeMode::Filter => self.filter(html),
+        }
+    }
+
+    fn filter(&self, html: &str) -> String {
+        let mut res = String::with_capacity(html.len());
+        let mut rest = html;
+        // dropped element whose content is skipped until its closing tag
+        let mut skip_until: Option<String> = None;
+
+        while let Some(lt) = rest.find('<') {
+            if skip_until.is_none() {
+                res.push_str(&rest[..lt]);
+            }
+            let after = &rest[lt..];
+
+            if after.starts_with("<!--") {
+                let end = after.find("-->").map_or(after.len(), |end| end + 3);
+                rest = &after[end..];
+                continue;
+            }
+
+            match parse_tag(after) {
+                Some((tag, len)) => {
+                    if let Some(name) = &skip_until {
+                        if tag.closing && tag.name == *name {
+                            skip_until = None;
+                        }
+                    } else if DROP_WITH_CONTENT.contains(&tag.name.as_str()) {
+                        if !tag.closing && !tag.self_closing {
+                            skip_until = Some(tag.name);
+                        }
+                    } else if self.tags.contains(&tag.name) {
+                        res += &self.render_tag(&tag);
+                    }
+                    rest = &after[len..];
+                }
+                None => {
+                    if skip_until.is_none() {
+                        res.push_str("&lt;");
+                    }
+                    rest = &after[1..];
+                }
+            }
+        }
+
+        if skip_until.is_none() {
+            res.push_str(rest);
+        }
+
+        res
+    }
+
+    // Applies the attribute allowlist to an attribute list written in Markdown
+    pub(crate) fn filter_attributes(&self, attributes: &Attributes) -> Attributes {
+        if self.mode == SafeMode::Off {
+            return attributes.clone();
+        }
+
+        let allowed = |name: &str| !name.starts_with("on") && self.attributes.contains(name);
+        Attributes {
+            id: if allowed("id") {
+                attributes.id.clone()
+            } else {
+                String::new()
+            },
+            classes: if allowed("class") {
+                attributes.classes.clone()
+            } else {
+                vec![]
+            },
+            pairs: attributes
+                .pairs
+                .iter()
+                .filter(|(name, value)| {
+                    let name = name.to_lowercase();
+                    allowed(&name)
+                        && (!URL_ATTRIBUTES.contains(&name.as_str()) || self.is_url_allowed(value))
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn render_tag(&self, tag: &Tag) -> String {
+        if tag.closing {
+            return format!("</{}>", tag.name);
+        }
+
+        let mut res = format!("<{}", tag.name);
+
+        for (name, value) in &tag.attributes {
+            // event handlers are never allowed, whatever the configuration says
+            if name.starts_with("on") || !self.attributes.contains(name) {
+                continue;
+            }
+
+            match value {
+                Some(value) => {
+                    if URL_ATTRIBUTES.contains(&name.as_str()) && !self.is_url_allowed(value) {
+                        continue;
+                    }
+                    res += &format!(" {}=\"{}\"", name, escape_attribute_value(value));
+                }
+                None => res += &format!(" {}", name),
+            }
+        }
+
+        res += if tag.self_closing { " />" } else { ">" };
+        res
+    }
+}
+
+// Attribute value taken from raw HTML is already encoded, so only unsafe characters are escaped
+fn escape_attribute_value(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    for (i, c) in value.char_indices() {
+        match c {
+            '&' if entity_len(&value[i..]).is_some() => res.push('&'),
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+// Decodes numeric entities and the named ones that can be used to hide a scheme
+fn decode_basic_entities(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut i = 0;
+
+    while i < s.len() {
+        let rest = &s[i..];
+        if let Some(len) = entity_len(rest) {
+            let name = &rest[1..len - 1];
+            let decoded = if let Some(code) = name.strip_prefix("#x").or(name.strip_prefix("#X")) {
+                u32::from_str_radix(code, 16).ok().and_then(char::from_u32)
+            } else if let Some(code) = name.strip_prefix('#') {
+                code.parse::<u32>().ok().and_then(char::from_u32)
+            } else {
+                match name.to_lowercase().as_str() {
+                    "colon" => Some(':'),
+                    "tab" => Some('\t'),
+                    "newline" => Some('\n'),
+                    "amp" => Some('&'),
+                    _ => None,
+                }
+            };
+
+            if let Some(c) = decoded {
+                res.push(c);
+                i += len;
+                continue;
+            }
+        }
+
+        let c = rest.chars().next().unwrap();
+        res.push(c);
+        i += c.len_utf8();
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let sanitizer = Sanitizer::new(SafeMode::Filter, None, None, None);
+
+        assert_eq!(
+            sanitizer.sanitize(
+                r#"<div class="x" onclick="evil()"><script>alert("<b>")</script><b>ok</b><!-- c --></div>"#
+            ),
+            r#"<div class="x"><b>ok</b></div>"#
+        );
+        assert_eq!(
+            sanitizer.sanitize(r#"<a href="javascript:alert(1)" title='a"b'>x</a><form>y</form>"#),
+            r#"<a title="a&quot;b">x</a>y"#
+        );
+        assert_eq!(
+            sanitizer.sanitize(r#"<img src="a.png?x=1&amp;y=2" />"#),
+            r#"<img src="a.png?x=1&amp;y=2" />"#
+        );
+        assert_eq!(sanitizer.sanitize("1 < 2"), "1 &lt; 2");
+    }
+
+    #[test]
+    fn test_urls() {
+        let sanitizer = Sanitizer::new(SafeMode::Filter, None, None, None);
+
+        assert!(sanitizer.is_url_allowed("https://askepit.github.io"));
+        assert!(sanitizer.is_url_allowed("/posts/voronoi?page=1#top"));
+        assert!(sanitizer.is_url_allowed("images/a:b.png"));
+        assert!(!sanitizer.is_url_allowed("JavaScript:alert(1)"));
+        assert!(!sanitizer.is_url_allowed(" java\tscript:alert(1)"));
+        assert!(!sanitizer.is_url_allowed("javascript&#58;alert(1)"));
+        assert!(!sanitizer.is_url_allowed("data:text/html,<script>"));
+
+        assert!(Sanitizer::default().is_url_allowed("javascript:alert(1)"));
+    }
+
+    #[test]
+    fn test_filter_attributes() {
+        let sanitizer = Sanitizer::new(SafeMode::Filter, None, None, None);
+        let attributes =
+            Attributes::parse("{#x .y onclick=evil() href=javascript:z title=t}").unwrap();
+
+        assert_eq!(
+            sanitizer.filter_attributes(&attributes).render(),
+            r#" id="x" class="y" title="t""#
+        );
+    }
+}
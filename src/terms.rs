@@ -0,0 +1,216 @@
+use crate::html::entity_len;
+use crate::{STASH_END, STASH_START};
+
+// Glossary term occurrence is marked up as `TERM_START index TERM_TEXT text TERM_END`. Only the
+// whole document knows which occurrence is the first one, so markers are resolved at the very end
+const TERM_START: char = '\u{E002}';
+const TERM_TEXT: char = '\u{E003}';
+const TERM_END: char = '\u{E004}';
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Length in bytes of `term` at the beginning of `text`
+fn match_term(text: &str, term: &str, ignore_case: bool) -> Option<usize> {
+    let mut text_chars = text.char_indices();
+
+    for term_char in term.chars() {
+        let (_, c) = text_chars.next()?;
+        let equal = if ignore_case {
+            c.to_lowercase().eq(term_char.to_lowercase())
+        } else {
+            c == term_char
+        };
+        if !equal {
+            return None;
+        }
+    }
+
+    Some(text_chars.next().map_or(text.len(), |(i, _)| i))
+}
+
+// Replaces whole-word occurrences of `terms` in escaped text. Longer terms go first, so
+// `Voronoi diagram` wins over `Voronoi`. `replace` gets a term index and the matched text
+pub(crate) fn replace_terms(
+    text: &str,
+    terms: &[String],
+    ignore_case: bool,
+    mut replace: impl FnMut(usize, &str) -> String,
+) -> String {
+    if terms.is_empty() {
+        return text.to_string();
+    }
+
+    let mut order = (0..terms.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(terms[i].len()));
+
+    let mut res = String::with_capacity(text.len());
+    let mut prev: Option<char> = None;
+    let mut i = 0;
+
+    'outer: while i < text.len() {
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap();
+
+        if !prev.is_some_and(is_word_char) {
+            for &term_index in &order {
+                let Some(len) = match_term(rest, &terms[term_index], ignore_case) else {
+                    continue;
+                };
+                if rest[len..].starts_with(is_word_char) {
+                    continue;
+                }
+
+                res += &replace(term_index, &rest[..len]);
+                prev = rest[..len].chars().last();
+                i += len;
+                continue 'outer;
+            }
+        }
+
+        // stash placeholders, term markers and entities are copied as is
+        let skip_len = match c {
+            STASH_START => rest.find(STASH_END).map(|end| end + STASH_END.len_utf8()),
+            TERM_START => rest.find(TERM_TEXT).map(|end| end + TERM_TEXT.len_utf8()),
+            '&' => entity_len(rest),
+            _ => None,
+        }
+        .unwrap_or(c.len_utf8());
+
+        res.push_str(&rest[..skip_len]);
+        prev = Some(c);
+        i += skip_len;
+    }
+
+    res
+}
+
+pub(crate) fn mark_term(index: usize, text: &str) -> String {
+    format!("{TERM_START}{index}{TERM_TEXT}{text}{TERM_END}")
+}
+
+// Resolves term markers in a whole document: `link` renders the first occurrence of every term,
+// the rest become plain text
+pub(crate) fn link_first_occurrences(
+    html: &str,
+    mut link: impl FnMut(usize, &str) -> String,
+) -> String {
+    let mut res = String::with_capacity(html.len());
+    let mut linked = vec![];
+    let mut rest = html;
+
+    while let Some(start) = rest.find(TERM_START) {
+        res.push_str(&rest[..start]);
+        let marker = &rest[start + TERM_START.len_utf8()..];
+
+        let (Some(text_start), Some(end)) = (marker.find(TERM_TEXT), marker.find(TERM_END)) else {
+            break;
+        };
+        let index = marker[..text_start].parse::<usize>().unwrap_or_default();
+        let text = &marker[text_start + TERM_TEXT.len_utf8()..end];
+
+        if linked.contains(&index) {
+            res.push_str(text);
+        } else {
+            linked.push(index);
+            res += &link(index, text);
+        }
+
+        rest = &marker[end + TERM_END.len_utf8()..];
+    }
+
+    res.push_str(rest);
+    res
+}
+
+// Drops markers keeping the text, for terms which end up inside an attribute value
+pub(crate) fn strip_term_markers(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut inside_marker = false;
+
+    for c in text.chars() {
+        match c {
+            TERM_START => inside_marker = true,
+            TERM_TEXT => inside_marker = false,
+            TERM_END => {}
+            _ if !inside_marker => res.push(c),
+            _ => {}
+        }
+    }
+
+    res
+}
+
+// Terms of a glossary written as a definition list: term lines followed by `: definition`
+pub(crate) fn parse_glossary(markdown: &str) -> Vec<String> {
+    let lines = markdown.lines().collect::<Vec<_>>();
+    let mut terms = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        if !line.starts_with(": ") || i == 0 || lines[i - 1].starts_with(": ") {
+            continue;
+        }
+
+        let first_term = lines[..i]
+            .iter()
+            .rposition(|x| x.trim().is_empty() || x.starts_with(' ') || x.starts_with(": "))
+            .map_or(0, |x| x + 1);
+        terms.extend(lines[first_term..i].iter().map(|x| x.trim().to_string()));
+    }
+
+    terms
+}
+
+// `Voronoi Diagram` -> `voronoi-diagram`
+pub(crate) fn anchor(term: &str) -> String {
+    term.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_terms() {
+        let terms = vec![
+            "HTML".to_string(),
+            "HTML page".to_string(),
+            "R&amp;D".to_string(),
+        ];
+        let wrap = |i: usize, text: &str| format!("[{i}:{text}]");
+
+        assert_eq!(
+            replace_terms("HTML, XHTML, HTML5 and HTML page", &terms, false, wrap),
+            "[0:HTML], XHTML, HTML5 and [1:HTML page]"
+        );
+        assert_eq!(
+            replace_terms("R&amp;D &amp;html \u{E000}1\u{E001}", &terms, true, wrap),
+            "[2:R&amp;D] &amp;[0:html] \u{E000}1\u{E001}"
+        );
+    }
+
+    #[test]
+    fn test_markers() {
+        let html = format!(
+            "<p title=\"{}\">{} and {}</p>",
+            strip_term_markers(&mark_term(0, "Tile")),
+            mark_term(0, "tile"),
+            mark_term(0, "Tile")
+        );
+
+        assert_eq!(
+            link_first_occurrences(&html, |i, text| format!("<a href=\"#{i}\">{text}</a>")),
+            "<p title=\"Tile\"><a href=\"#0\">tile</a> and Tile</p>"
+        );
+        assert_eq!(
+            parse_glossary("# Glossary\n\nTile\nCell\n: A hexagon\n: Or a square\n\n    More\n\nVoronoi Diagram\n: Partition"),
+            vec!["Tile", "Cell", "Voronoi Diagram"]
+        );
+        assert_eq!(anchor("Voronoi Diagram (2D)"), "voronoi-diagram-2d");
+    }
+}
@@ -0,0 +1,141 @@
+use crate::configurator::parse_highlight;
+use crate::toml_parser::TomlDoc;
+
+// CSS declarations per highlight class
+pub(crate) type Theme = &'static [(&'static str, &'static str)];
+
+// Built-in colour themes for highlighted code
+const THEMES: &[(&str, Theme)] = &[
+    (
+        "light",
+        &[
+            ("keyword", "color: #cf222e"),
+            ("type", "color: #953800"),
+            ("literal", "color: #0a3069"),
+            ("comment", "color: #6e7781; font-style: italic"),
+            ("call", "color: #8250df"),
+            ("macro", "color: #8250df"),
+            ("attribute", "color: #116329"),
+            ("preprocessor", "color: #cf222e"),
+            ("operator", "color: #0550ae"),
+            ("punctuation", "color: #57606a"),
+            ("variable", "color: #953800"),
+            ("key", "color: #0550ae"),
+            ("tag", "color: #116329"),
+            ("attr-name", "color: #0550ae"),
+            ("diff-add", "color: #116329; background-color: #dafbe1"),
+            ("diff-remove", "color: #82071e; background-color: #ffebe9"),
+            ("diff-header", "color: #24292f; font-weight: bold"),
+            ("diff-hunk", "color: #8250df"),
+            ("diff-add-word", "background-color: #aceebb"),
+            ("diff-remove-word", "background-color: #ffc1c0"),
+        ],
+    ),
+    (
+        "dark",
+        &[
+            ("keyword", "color: #c678dd"),
+            ("type", "color: #e5c07b"),
+            ("literal", "color: #98c379"),
+            ("comment", "color: #7f848e; font-style: italic"),
+            ("call", "color: #61afef"),
+            ("macro", "color: #56b6c2"),
+            ("attribute", "color: #d19a66"),
+            ("preprocessor", "color: #c678dd"),
+            ("operator", "color: #56b6c2"),
+            ("punctuation", "color: #abb2bf"),
+            ("variable", "color: #e06c75"),
+            ("key", "color: #e06c75"),
+            ("tag", "color: #e06c75"),
+            ("attr-name", "color: #d19a66"),
+            ("diff-add", "color: #98c379; background-color: #2b3b2c"),
+            ("diff-remove", "color: #e06c75; background-color: #3f2a2d"),
+            ("diff-header", "color: #abb2bf; font-weight: bold"),
+            ("diff-hunk", "color: #61afef"),
+            ("diff-add-word", "background-color: #3d5a3f"),
+            ("diff-remove-word", "background-color: #613a3e"),
+        ],
+    ),
+    (
+        "solarized",
+        &[
+            ("keyword", "color: #859900"),
+            ("type", "color: #b58900"),
+            ("literal", "color: #2aa198"),
+            ("comment", "color: #93a1a1; font-style: italic"),
+            ("call", "color: #268bd2"),
+            ("macro", "color: #cb4b16"),
+            ("attribute", "color: #6c71c4"),
+            ("preprocessor", "color: #cb4b16"),
+            ("operator", "color: #657b83"),
+            ("punctuation", "color: #586e75"),
+            ("variable", "color: #268bd2"),
+            ("key", "color: #268bd2"),
+            ("tag", "color: #268bd2"),
+            ("attr-name", "color: #b58900"),
+            ("diff-add", "color: #859900; background-color: #eee8d5"),
+            ("diff-remove", "color: #dc322f; background-color: #eee8d5"),
+            ("diff-header", "color: #586e75; font-weight: bold"),
+            ("diff-hunk", "color: #6c71c4"),
+            ("diff-add-word", "background-color: #d9e0b3"),
+            ("diff-remove-word", "background-color: #f2cfc4"),
+        ],
+    ),
+];
+
+pub(crate) fn find_theme(name: &str) -> Option<Theme> {
+    THEMES
+        .iter()
+        .find(|(x, _)| x.eq_ignore_ascii_case(name))
+        .map(|(_, theme)| *theme)
+}
+
+pub(crate) fn theme_style(theme: Theme, class: &str) -> Option<&'static str> {
+    theme
+        .iter()
+        .find(|(x, _)| *x == class)
+        .map(|(_, style)| *style)
+}
+
+pub fn theme_names() -> Vec<&'static str> {
+    THEMES.iter().map(|(name, _)| *name).collect()
+}
+
+// Stylesheet of a built-in theme for the classes of the `[highlight]` section of `config_toml`,
+// the default `code-*` ones if it's not given
+pub fn theme_css(name: &str, config_toml: Option<&str>) -> Option<String> {
+    let theme = find_theme(name)?;
+    let markup = config_toml
+        .and_then(|x| parse_highlight(&TomlDoc::new(x), &mut vec![]))
+        .unwrap_or_default();
+    let mut res = format!(
+        "/* downtown code highlighting: {} theme */\n",
+        name.to_lowercase()
+    );
+    for (class, style) in theme {
+        if let Some(selector) = markup.selector(class) {
+            res += &format!("{selector} {{ {style}; }}\n");
+        }
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_css() {
+        let css = theme_css("Dark", None).unwrap();
+        assert!(css.starts_with("/* downtown code highlighting: dark theme */\n"));
+        assert!(css.contains("\n.code-keyword { color: #c678dd; }\n"));
+        assert!(theme_css("unknown", None).is_none());
+
+        let config = "[highlight]\nkeyword = '<b class=\"hljs-keyword x\">{text}</b>'\ntype = '<b>{text}</b>'";
+        let css = theme_css("dark", Some(config)).unwrap();
+        assert!(css.contains("\n.hljs-keyword.x { color: #c678dd; }\n"));
+        assert!(!css.contains("color: #e5c07b"));
+        assert!(css.contains("\n.code-literal { color: #98c379; }\n"));
+        assert_eq!(theme_names(), vec!["light", "dark", "solarized"]);
+    }
+}
@@ -80,16 +80,23 @@ impl TomlDoc {
                     current_key = Some(key);
                     multiline_value.push_str(stripped);
                     multiline_value.push('\n');
-                } else if value.starts_with('\'') && value.ends_with('\'') {
-                    // Single-quoted string
-                    let stripped_value = value[1..value.len() - 1].to_string();
-                    if let Some(table) = &current_table {
-                        self.tables
-                            .get_mut(table)
-                            .unwrap()
-                            .entries
-                            .insert(key, stripped_value);
-                    }
+                } else if let Some(table) = &current_table {
+                    let value = if value.len() >= 2
+                        && ((value.starts_with('\'') && value.ends_with('\''))
+                            || (value.starts_with('"') && value.ends_with('"')))
+                    {
+                        // Single- or double-quoted string
+                        value[1..value.len() - 1].to_string()
+                    } else {
+                        // Bare value: booleans, numbers
+                        value.to_string()
+                    };
+
+                    self.tables
+                        .get_mut(table)
+                        .unwrap()
+                        .entries
+                        .insert(key, value);
                 }
             }
         }
@@ -98,6 +105,18 @@ impl TomlDoc {
     pub(crate) fn get(&self, table_name: &str, variable_name: &str) -> Option<&String> {
         self.tables.get(table_name)?.entries.get(variable_name)
     }
+
+    pub(crate) fn get_bool(&self, table_name: &str, variable_name: &str) -> Option<bool> {
+        match self.get(table_name, variable_name)?.as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn table(&self, table_name: &str) -> Option<&TomlTable> {
+        self.tables.get(table_name)
+    }
 }
 
 #[cfg(test)]
@@ -173,4 +192,27 @@ error = '<div class="parse-error">{text}</div>'
         assert_eq!(parser.get("page", "epilog"), None);
         assert_eq!(parser.get("tags", "epilogue"), None);
     }
+
+    #[test]
+    fn test_bare_values() {
+        let toml_content = r#"
+[options]
+emoji = true
+threads = 4
+name = "double quoted"
+broken = yes
+
+[emoji]
+shipit = ':squirrel:'
+"#;
+
+        let parser = TomlDoc::new(toml_content);
+
+        assert_eq!(parser.get_bool("options", "emoji"), Some(true));
+        assert_eq!(parser.get_bool("options", "broken"), None);
+        assert_eq!(parser.get_bool("options", "missing"), None);
+        assert_eq!(*parser.get("options", "threads").unwrap(), "4");
+        assert_eq!(*parser.get("options", "name").unwrap(), "double quoted");
+        assert_eq!(parser.table("emoji").unwrap().entries.len(), 1);
+    }
 }
@@ -0,0 +1,167 @@
+const NBSP: char = '\u{a0}';
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct QuoteStyle {
+    primary: (char, char),
+    secondary: (char, char),
+}
+
+impl QuoteStyle {
+    // Parses a style written as four characters: primary open/close, secondary open/close
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let chars = s.chars().collect::<Vec<_>>();
+        if chars.len() != 4 {
+            return None;
+        }
+
+        Some(QuoteStyle {
+            primary: (chars[0], chars[1]),
+            secondary: (chars[2], chars[3]),
+        })
+    }
+
+    pub(crate) fn builtin(lang: &str) -> Self {
+        let style = match lang {
+            "ru" | "uk" | "be" => "«»„“",
+            "de" | "cs" => "„“‚‘",
+            "fr" => "«»‹›",
+            "pl" => "„”«»",
+            _ => "“”‘’",
+        };
+        QuoteStyle::parse(style).unwrap()
+    }
+}
+
+// `en-US` and `ru_RU` are treated as `en` and `ru`
+pub(crate) fn primary_lang(lang: &str) -> String {
+    lang.split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
+// Languages where one- and two-letter words (mostly prepositions and conjunctions) must not be
+// left hanging at the end of a line
+fn binds_short_words(lang: &str) -> bool {
+    matches!(lang, "ru" | "uk" | "be" | "pl" | "cs")
+}
+
+fn is_opening_context(prev: Option<char>) -> bool {
+    match prev {
+        None => true,
+        Some(c) => c.is_whitespace() || "([{<-–—«„“‘‚".contains(c),
+    }
+}
+
+pub(crate) fn smarten(
+    text: &str,
+    quotes: &QuoteStyle,
+    lang: &str,
+    non_breaking_spaces: bool,
+) -> String {
+    let text = text
+        .replace("...", "…")
+        .replace("---", "—")
+        .replace("--", "–");
+
+    let mut res = String::with_capacity(text.len());
+    let mut prev: Option<char> = None;
+    let mut double_depth = 0usize;
+
+    for c in text.chars() {
+        let c = match c {
+            '"' => {
+                // quotes nested into other quotes use the secondary pair
+                if is_opening_context(prev) {
+                    double_depth += 1;
+                    if double_depth > 1 {
+                        quotes.secondary.0
+                    } else {
+                        quotes.primary.0
+                    }
+                } else {
+                    let close = if double_depth > 1 {
+                        quotes.secondary.1
+                    } else {
+                        quotes.primary.1
+                    };
+                    double_depth = double_depth.saturating_sub(1);
+                    close
+                }
+            }
+            '\'' => {
+                if prev.is_some_and(|p| p.is_alphanumeric()) {
+                    '’' // apostrophe
+                } else if is_opening_context(prev) {
+                    quotes.secondary.0
+                } else {
+                    quotes.secondary.1
+                }
+            }
+            _ => c,
+        };
+
+        res.push(c);
+        prev = Some(c);
+    }
+
+    if non_breaking_spaces {
+        res = bind_with_non_breaking_spaces(&res, binds_short_words(lang));
+    }
+
+    res
+}
+
+fn bind_with_non_breaking_spaces(text: &str, short_words: bool) -> String {
+    let words = text.split(' ').collect::<Vec<_>>();
+    let mut res = String::with_capacity(text.len());
+
+    for (i, word) in words.iter().enumerate() {
+        res.push_str(word);
+
+        if i + 1 == words.len() {
+            break;
+        }
+
+        let next = words[i + 1];
+        let dash_follows = next.starts_with(['—', '–']);
+        let short_word = short_words
+            && !word.is_empty()
+            && word.chars().count() <= 2
+            && word.chars().all(char::is_alphabetic);
+
+        res.push(if dash_follows || short_word {
+            NBSP
+        } else {
+            ' '
+        });
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smarten() {
+        let en = QuoteStyle::builtin("en");
+        let ru = QuoteStyle::builtin(&primary_lang("ru-RU"));
+
+        assert_eq!(
+            smarten(r#"He said "it's 'fine'"... 1--2 --- ok"#, &en, "en", false),
+            "He said “it’s ‘fine’”… 1–2 — ok"
+        );
+        assert_eq!(
+            smarten(r#""Ёлочки "лапки" и" всё"#, &ru, "ru", false),
+            "«Ёлочки „лапки“ и» всё"
+        );
+        assert_eq!(
+            smarten("Кот и пёс -- друзья", &ru, "ru", true),
+            "Кот и\u{a0}пёс\u{a0}– друзья"
+        );
+        assert_eq!(QuoteStyle::parse("«»"), None);
+    }
+}